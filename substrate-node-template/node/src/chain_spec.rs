@@ -152,5 +152,7 @@ fn testnet_genesis(
 			key: Some(root_key),
 		},
 		transaction_payment: Default::default(),
+		treasury: Default::default(),
+		asset_tracking: Default::default(),
 	}
 }
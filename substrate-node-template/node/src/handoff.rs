@@ -0,0 +1,22 @@
+//! Sealing of handoff keys to the next holder of a shipment.
+//!
+//! The scheme itself lives in `pallet_template::sealing`, so keys sealed here and keys the
+//! runtime rotates on its own can be opened the same way. This module supplies the fresh
+//! ephemeral keys and the keystore pairs.
+
+use node_template_runtime::pallet_template::{sealing, SealedKey};
+use sp_core::{ed25519, Pair};
+
+/// Encrypt `key` to the holder of the ed25519 key `recipient`.
+///
+/// Returns `None` if `recipient` is not a valid curve point.
+pub fn seal(recipient: &ed25519::Public, key: &[u8; 16]) -> Option<SealedKey> {
+	let (ephemeral, _) = ed25519::Pair::generate();
+	sealing::seal(&recipient.0, ephemeral.seed(), key)
+}
+
+/// Decrypt a key sealed to `pair`. Returns `None` if it was sealed to another key or tampered
+/// with.
+pub fn open(pair: &ed25519::Pair, sealed: &SealedKey) -> Option<[u8; 16]> {
+	sealing::open(pair.seed(), sealed)
+}
@@ -1,3 +1,4 @@
 pub mod chain_spec;
+pub mod handoff;
 pub mod rpc;
 pub mod service;
@@ -7,6 +7,7 @@ mod service;
 mod cli;
 mod command;
 mod command_helper;
+mod handoff;
 mod rpc;
 
 fn main() -> sc_cli::Result<()> {
@@ -7,12 +7,23 @@
 
 use std::sync::Arc;
 
-use node_template_runtime::{opaque::Block, AccountId, Balance, Index};
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result as RpcResult};
+use jsonrpc_derive::rpc;
+use node_template_runtime::{
+	opaque::Block,
+	pallet_template::{crypto, SealedKey, KEY_TYPE},
+	AccountId, Balance, BlockNumber, Index, Moment,
+};
+use pallet_template_runtime_api::AssetTrackingApi as AssetTrackingRuntimeApi;
+use sc_keystore::LocalKeystore;
 pub use sc_rpc_api::DenyUnsafe;
 use sc_transaction_pool_api::TransactionPool;
 use sp_api::ProvideRuntimeApi;
 use sp_block_builder::BlockBuilder;
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
+use sp_core::{ed25519, H256};
+use sp_keystore::SyncCryptoStore;
+use sp_runtime::generic::BlockId;
 
 /// Full client dependencies.
 pub struct FullDeps<C, P> {
@@ -20,6 +31,8 @@ pub struct FullDeps<C, P> {
 	pub client: Arc<C>,
 	/// Transaction pool instance.
 	pub pool: Arc<P>,
+	/// The node's local keystore, holding its handoff key.
+	pub keystore: Option<Arc<LocalKeystore>>,
 	/// Whether to deny unsafe calls
 	pub deny_unsafe: DenyUnsafe,
 }
@@ -33,22 +46,94 @@ where
 	C::Api: substrate_frame_rpc_system::AccountNonceApi<Block, AccountId, Index>,
 	C::Api: pallet_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance>,
 	C::Api: BlockBuilder<Block>,
+	C::Api: AssetTrackingRuntimeApi<Block, AccountId, BlockNumber, Moment>,
 	P: TransactionPool + 'static,
 {
 	use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApi};
 	use substrate_frame_rpc_system::{FullSystem, SystemApi};
 
 	let mut io = jsonrpc_core::IoHandler::default();
-	let FullDeps { client, pool, deny_unsafe } = deps;
+	let FullDeps { client, pool, keystore, deny_unsafe } = deps;
 
 	io.extend_with(SystemApi::to_delegate(FullSystem::new(client.clone(), pool, deny_unsafe)));
 
 	io.extend_with(TransactionPaymentApi::to_delegate(TransactionPayment::new(client.clone())));
 
-	// Extend this RPC with a custom API by using the following syntax.
-	// `YourRpcStruct` should have a reference to a client, which is needed
-	// to call into the runtime.
-	// `io.extend_with(YourRpcTrait::to_delegate(YourRpcStruct::new(ReferenceToClient, ...)));`
+	io.extend_with(AssetTrackingApi::to_delegate(AssetTracking::new(
+		client.clone(),
+		keystore,
+		deny_unsafe,
+	)));
 
 	io
 }
+
+/// Helpers for handing off shipments without exposing handoff keys on chain.
+#[rpc]
+pub trait AssetTrackingApi<BlockHash> {
+	/// Open the handoff key of a shipment with whichever registered handoff key this node holds.
+	#[rpc(name = "assetTracking_openHandoffKey")]
+	fn open_handoff_key(&self, shipment_uid: u64, at: Option<BlockHash>) -> RpcResult<[u8; 16]>;
+
+	/// Seal a handoff key to the registered handoff key of the next holder. The key is sent to
+	/// the node in the clear, so this is an unsafe RPC; remote clients should seal keys
+	/// themselves with [`crate::handoff::seal`].
+	#[rpc(name = "assetTracking_sealHandoffKey")]
+	fn seal_handoff_key(&self, recipient: H256, key: [u8; 16]) -> RpcResult<SealedKey>;
+}
+
+/// Implements [`AssetTrackingApi`] on top of the runtime API and the local keystore.
+pub struct AssetTracking<C> {
+	client: Arc<C>,
+	keystore: Option<Arc<LocalKeystore>>,
+	deny_unsafe: DenyUnsafe,
+}
+
+impl<C> AssetTracking<C> {
+	/// Create new `AssetTracking` with the given reference to the client and keystore.
+	pub fn new(client: Arc<C>, keystore: Option<Arc<LocalKeystore>>, deny_unsafe: DenyUnsafe) -> Self {
+		AssetTracking { client, keystore, deny_unsafe }
+	}
+}
+
+fn rpc_error(message: &str) -> RpcError {
+	RpcError { code: ErrorCode::ServerError(1), message: message.into(), data: None }
+}
+
+impl<C> AssetTrackingApi<<Block as sp_runtime::traits::Block>::Hash> for AssetTracking<C>
+where
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: AssetTrackingRuntimeApi<Block, AccountId, BlockNumber, Moment>,
+{
+	fn open_handoff_key(
+		&self,
+		shipment_uid: u64,
+		at: Option<<Block as sp_runtime::traits::Block>::Hash>,
+	) -> RpcResult<[u8; 16]> {
+		self.deny_unsafe.check_if_safe()?;
+
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let sealed = self
+			.client
+			.runtime_api()
+			.sealed_key(&at, shipment_uid)
+			.map_err(|e| rpc_error(&format!("Unable to query sealed key: {:?}", e)))?
+			.ok_or_else(|| rpc_error("Shipment has no sealed handoff key"))?;
+
+		let keystore = self.keystore.as_ref().ok_or_else(|| rpc_error("No local keystore"))?;
+		SyncCryptoStore::ed25519_public_keys(&**keystore, KEY_TYPE)
+			.into_iter()
+			.filter_map(|public| {
+				keystore.key_pair::<crypto::Pair>(&crypto::Public::from(public)).ok().flatten()
+			})
+			.find_map(|pair| crate::handoff::open(&pair.into(), &sealed))
+			.ok_or_else(|| rpc_error("Handoff key is not sealed to this node"))
+	}
+
+	fn seal_handoff_key(&self, recipient: H256, key: [u8; 16]) -> RpcResult<SealedKey> {
+		self.deny_unsafe.check_if_safe()?;
+
+		crate::handoff::seal(&ed25519::Public::from_raw(recipient.0), &key)
+			.ok_or_else(|| rpc_error("Recipient is not a valid ed25519 key"))
+	}
+}
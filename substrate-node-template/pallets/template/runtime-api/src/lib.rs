@@ -0,0 +1,24 @@
+//! Runtime API for reading shipment history straight from state.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use pallet_template::{CustodyEntry, SealedKey, TitleEntry};
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+	pub trait AssetTrackingApi<AccountId, BlockNumber, Moment> where
+		AccountId: Codec,
+		BlockNumber: Codec,
+		Moment: Codec,
+	{
+		/// Every handoff of a shipment in order, starting with its creation.
+		fn custody_log(shipment_uid: u64) -> Vec<CustodyEntry<AccountId, BlockNumber, Moment>>;
+
+		/// The current handoff key of a shipment, sealed to its next holder.
+		fn sealed_key(shipment_uid: u64) -> Option<SealedKey>;
+
+		/// Every owner of a shipment's goods in order, starting with whoever paid for it.
+		fn title_history(shipment_uid: u64) -> Vec<TitleEntry<AccountId, BlockNumber>>;
+	}
+}
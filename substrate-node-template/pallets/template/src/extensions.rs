@@ -0,0 +1,196 @@
+//! Transaction extensions for asset tracking calls.
+
+use crate::{Call, Config, Pallet};
+use codec::{Decode, Encode};
+use frame_support::{
+	dispatch::DispatchResult,
+	ensure,
+	traits::IsSubType,
+	weights::{DispatchInfo, Pays, PostDispatchInfo},
+};
+use scale_info::TypeInfo;
+use sp_runtime::{
+	traits::{DispatchInfoOf, Dispatchable, PostDispatchInfoOf, SignedExtension},
+	transaction_validity::{InvalidTransaction, TransactionValidity, TransactionValidityError, ValidTransaction},
+};
+use sp_std::{collections::btree_map::BTreeMap, marker::PhantomData};
+
+// Prefix of the tags handoffs provide and require in the transaction pool
+const HANDOFF_TAG_PREFIX: &str = "AssetTrackingHandoff";
+
+/// Wraps the runtime's transaction payment extension `S` so that handoffs by the legitimate
+/// next holder of a shipment are free.
+///
+/// The fee is still withdrawn before dispatch. It is refunded when an `update_shipment` by
+/// the node the shipment is being handed to succeeds, for up to `MaxFeelessHandoffs` handoffs
+/// per node and era. Failing calls and handoffs over the limit pay as usual. The extension
+/// encodes exactly like `S` and keeps its identifier, so wallets treat it as `S`.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct FeelessHandoff<T, S>(pub S, PhantomData<T>);
+
+impl<T, S> From<S> for FeelessHandoff<T, S> {
+	fn from(inner: S) -> Self {
+		Self(inner, PhantomData)
+	}
+}
+
+impl<T, S: sp_std::fmt::Debug> sp_std::fmt::Debug for FeelessHandoff<T, S> {
+	fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+		write!(f, "FeelessHandoff({:?})", self.0)
+	}
+}
+
+impl<T, S> SignedExtension for FeelessHandoff<T, S>
+where
+	T: Config + Send + Sync,
+	<T as frame_system::Config>::Call: IsSubType<Call<T>>,
+	S: SignedExtension<AccountId = T::AccountId, Call = <T as frame_system::Config>::Call>,
+	S::Call: Dispatchable<Info = DispatchInfo, PostInfo = PostDispatchInfo>,
+{
+	const IDENTIFIER: &'static str = S::IDENTIFIER;
+	type AccountId = T::AccountId;
+	type Call = S::Call;
+	type AdditionalSigned = S::AdditionalSigned;
+	// The inner extension's `Pre` and the node to refund if the handoff succeeds
+	type Pre = (S::Pre, Option<T::AccountId>);
+
+	fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+		self.0.additional_signed()
+	}
+
+	fn validate(
+		&self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		info: &DispatchInfoOf<Self::Call>,
+		len: usize,
+	) -> TransactionValidity {
+		self.0.validate(who, call, info, len)
+	}
+
+	fn pre_dispatch(
+		self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		info: &DispatchInfoOf<Self::Call>,
+		len: usize,
+	) -> Result<Self::Pre, TransactionValidityError> {
+		let feeless = Pallet::<T>::is_feeless_handoff(who, call).then(|| who.clone());
+		Ok((self.0.pre_dispatch(who, call, info, len)?, feeless))
+	}
+
+	fn post_dispatch(
+		pre: Option<Self::Pre>,
+		info: &DispatchInfoOf<Self::Call>,
+		post_info: &PostDispatchInfoOf<Self::Call>,
+		len: usize,
+		result: &DispatchResult,
+	) -> Result<(), TransactionValidityError> {
+		match pre {
+			Some((pre, Some(node))) if result.is_ok() => {
+				Pallet::<T>::note_feeless_handoff(&node);
+				let mut post_info = *post_info;
+				post_info.pays_fee = Pays::No;
+				S::post_dispatch(Some(pre), info, &post_info, len, result)
+			},
+			Some((pre, _)) => S::post_dispatch(Some(pre), info, post_info, len, result),
+			None => S::post_dispatch(None, info, post_info, len, result),
+		}
+	}
+}
+
+/// Rejects handoffs by the wrong node or with a wrong key or signatures in the transaction
+/// pool, before they pay for block space only to fail at dispatch.
+///
+/// Valid handoffs provide a `(shipment_uid, hop)` tag, so the pool keeps only one of several
+/// competing handoffs of the same hop. Vouchers for hops past a shipment's current one require
+/// the tag of the hop before and wait in the pool until that handoff is included.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct CheckHandoff<T>(PhantomData<T>);
+
+impl<T> CheckHandoff<T> {
+	pub fn new() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<T> Default for CheckHandoff<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> sp_std::fmt::Debug for CheckHandoff<T> {
+	fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+		write!(f, "CheckHandoff")
+	}
+}
+
+impl<T> SignedExtension for CheckHandoff<T>
+where
+	T: Config + Send + Sync,
+	<T as frame_system::Config>::Call: IsSubType<Call<T>>,
+{
+	const IDENTIFIER: &'static str = "CheckHandoff";
+	type AccountId = T::AccountId;
+	type Call = <T as frame_system::Config>::Call;
+	type AdditionalSigned = ();
+	type Pre = ();
+
+	fn additional_signed(&self) -> Result<Self::AdditionalSigned, TransactionValidityError> {
+		Ok(())
+	}
+
+	fn validate(
+		&self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		_info: &DispatchInfoOf<Self::Call>,
+		_len: usize,
+	) -> TransactionValidity {
+		match call.is_sub_type() {
+			Some(Call::update_shipment { shipment_uid, proof, .. }) => {
+				let hop = Pallet::<T>::check_handoff(who, *shipment_uid, proof)?;
+				ValidTransaction::with_tag_prefix(HANDOFF_TAG_PREFIX)
+					.and_provides((shipment_uid, hop))
+					.build()
+			},
+			Some(Call::submit_handoffs { vouchers }) => {
+				let mut valid = ValidTransaction::with_tag_prefix(HANDOFF_TAG_PREFIX);
+				let mut last_hops = BTreeMap::new();
+				for voucher in vouchers.iter() {
+					let current_hop = Pallet::<T>::check_voucher(voucher)?;
+					match last_hops.insert(voucher.shipment_uid, voucher.hop) {
+						// Several hops of one shipment are applied in route order
+						Some(last_hop) => ensure!(
+							last_hop.checked_add(1) == Some(voucher.hop),
+							InvalidTransaction::Call
+						),
+						None if voucher.hop > current_hop =>
+							valid = valid.and_requires((voucher.shipment_uid, voucher.hop - 1)),
+						None => {},
+					}
+					valid = valid.and_provides((voucher.shipment_uid, voucher.hop));
+				}
+				valid.build()
+			},
+			_ => Ok(ValidTransaction::default()),
+		}
+	}
+
+	fn pre_dispatch(
+		self,
+		who: &Self::AccountId,
+		call: &Self::Call,
+		info: &DispatchInfoOf<Self::Call>,
+		len: usize,
+	) -> Result<Self::Pre, TransactionValidityError> {
+		// Inside a block, the handoffs a transaction waited for must have been applied already
+		match self.validate(who, call, info, len)?.requires.is_empty() {
+			true => Ok(()),
+			false => Err(InvalidTransaction::Future.into()),
+		}
+	}
+}
@@ -0,0 +1,102 @@
+//! Fixed-point great-circle maths used to derive edge costs from node coordinates.
+//!
+//! All angles are represented as radians scaled by `ONE` (nano-radians) so that the
+//! runtime never touches floating point.
+
+use codec::{Decode, Encode, MaxEncodedLen};
+use frame_support::RuntimeDebug;
+use scale_info::TypeInfo;
+use sp_runtime::traits::IntegerSquareRoot;
+
+#[cfg(feature = "std")]
+use frame_support::serde::{Deserialize, Serialize};
+
+const ONE: i64 = 1_000_000_000;
+const PI: i64 = 3_141_592_654;
+const HALF_PI: i64 = 1_570_796_327;
+const TWO_PI: i64 = 6_283_185_307;
+const MICRODEGREES_PER_HALF_TURN: i128 = 180_000_000;
+const EARTH_RADIUS_METRES: i128 = 6_371_000;
+
+/// Position of a transit node, in millionths of a degree.
+#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Coordinates {
+	pub lat: i32,
+	pub lon: i32,
+}
+
+impl Coordinates {
+	pub fn is_valid(&self) -> bool {
+		self.lat.abs() <= 90_000_000 && self.lon.abs() <= 180_000_000
+	}
+}
+
+fn mul(a: i64, b: i64) -> i64 {
+	(a as i128 * b as i128 / ONE as i128) as i64
+}
+
+fn to_radians(microdegrees: i32) -> i64 {
+	(microdegrees as i128 * PI as i128 / MICRODEGREES_PER_HALF_TURN) as i64
+}
+
+fn sin(x: i64) -> i64 {
+	// Reduce to [-PI, PI], then fold onto [-PI/2, PI/2] where the series converges quickly.
+	let mut x = x % TWO_PI;
+	if x > PI {
+		x -= TWO_PI;
+	} else if x < -PI {
+		x += TWO_PI;
+	}
+	if x > HALF_PI {
+		x = PI - x;
+	} else if x < -HALF_PI {
+		x = -PI - x;
+	}
+
+	let x2 = mul(x, x);
+	let mut term = x;
+	let mut sum = x;
+	for n in 1..8i64 {
+		term = -mul(term, x2) / ((2 * n) * (2 * n + 1));
+		sum += term;
+	}
+	sum
+}
+
+fn cos(x: i64) -> i64 {
+	sin(x + HALF_PI)
+}
+
+fn sqrt(x: i64) -> i64 {
+	(x.max(0) as u128 * ONE as u128).integer_sqrt() as i64
+}
+
+// Inverse of `sin` on [0, 1] by bisection, sin being monotonic on [0, PI/2].
+fn asin(x: i64) -> i64 {
+	let (mut lo, mut hi) = (0, HALF_PI);
+	while hi - lo > 1 {
+		let mid = (lo + hi) / 2;
+		if sin(mid) < x {
+			lo = mid;
+		} else {
+			hi = mid;
+		}
+	}
+	lo
+}
+
+/// Great-circle distance between two points in metres, using the haversine formula.
+pub fn great_circle_distance(from: &Coordinates, to: &Coordinates) -> u32 {
+	let lat1 = to_radians(from.lat);
+	let lat2 = to_radians(to.lat);
+	let half_dlat = (lat2 - lat1) / 2;
+	let half_dlon = (to_radians(to.lon) - to_radians(from.lon)) / 2;
+
+	let sin_dlat = sin(half_dlat);
+	let sin_dlon = sin(half_dlon);
+	let a = mul(sin_dlat, sin_dlat) + mul(mul(cos(lat1), cos(lat2)), mul(sin_dlon, sin_dlon));
+	let central_angle = 2 * asin(sqrt(a.clamp(0, ONE)));
+
+	(central_angle as i128 * EARTH_RADIUS_METRES / ONE as i128) as u32
+}
@@ -2,6 +2,24 @@
 
   pub use pallet::*;
 
+  use sp_core::crypto::KeyTypeId;
+
+  pub mod extensions;
+  pub mod geo;
+  pub mod migrations;
+  pub mod nft;
+  pub mod sealing;
+
+  /// Key type under which transit nodes keep the key handoff keys are sealed to.
+  pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"trak");
+
+  /// Ed25519 keys used to seal handoff keys. Sealing converts them to their x25519 form.
+  pub mod crypto {
+	use super::KEY_TYPE;
+	use sp_runtime::app_crypto::{app_crypto, ed25519};
+	app_crypto!(ed25519, KEY_TYPE);
+  }
+
   #[cfg(test)]
   mod mock;
 
@@ -11,59 +29,398 @@
   #[frame_support::pallet]
   pub mod pallet {
 
+	// Blocks after which the offchain worker resubmits route vectors that were not included
+	const ROUTE_VECTOR_RESUBMIT_BLOCKS: u32 = 5;
+	// Offchain index key prefix under which reaped shipment summaries are stored
+	const ARCHIVE_INDEX_PREFIX: &[u8] = b"asset_tracking::archive";
+	// Prefixed to voucher payloads so they can't be mistaken for online handoff payloads
+	const VOUCHER_CONTEXT: &[u8] = b"asset_tracking::voucher";
+	// Prefixed to online handoff payloads so signatures over them can't be taken from elsewhere
+	const HANDOFF_CONTEXT: &[u8] = b"asset_tracking::handoff";
+
 	use frame_support::{
 		pallet_prelude::*,
-		traits::{Currency, Randomness},
+		traits::{
+			BalanceStatus, Currency, ExistenceRequirement, IsSubType, OnUnbalanced, Randomness,
+			ReservableCurrency, WithdrawReasons,
+		},
+		transactional,
 		BoundedVec,
+		PalletId,
+	};
+	use frame_system::{
+		offchain::{SendTransactionTypes, SubmitTransaction},
+		pallet_prelude::*,
 	};
-	use frame_system::pallet_prelude::*;
 	use scale_info::{
 		TypeInfo,
 	};
-	use sp_io::hashing::blake2_128;
-	use sp_runtime::ArithmeticError;
-	use sp_std::{vec,vec::Vec};
+	use sp_runtime::{
+		offchain::storage::StorageValueRef,
+		traits::{AccountIdConversion, Hash, IdentifyAccount, Saturating, Verify, Zero},
+		ArithmeticError,
+		Permill,
+	};
+	use sp_io::hashing::{blake2_128, blake2_256};
+	use sp_std::{collections::btree_map::BTreeMap, vec, vec::Vec};
+
+	use crate::{crypto, geo::{self, Coordinates}, nft::ShipmentItems, sealing};
 
 
 	#[cfg(feature = "std")]
 	use frame_support::serde::{Deserialize, Serialize};
 
 	type BalanceOf<T> = <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+	type NegativeImbalanceOf<T> =
+		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::NegativeImbalance;
 
 	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
 	#[scale_info(skip_type_params(T))]
 	pub struct Shipment<T: Config> {
 		pub creator: T::AccountId,
+		/// Owner of the goods, independent of who has custody of them.
+		pub beneficial_owner: T::AccountId,
 		pub fees: Option<BalanceOf<T>>,
 		pub owner_index: u8,
 		pub route: BoundedVec<T::AccountId,T::MaxSize>,
 		pub destination: T::AccountId,
 		pub uid: u64,
 		pub status: ShipmentStatus,
+		pub handling: HandlingClass,
+		pub consignee: Option<T::AccountId>,
+		pub handoff_mode: HandoffMode,
+	}
+
+	/// One handoff of a shipment. `hop` is the index of `handler` in the shipment's route.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct CustodyEntry<AccountId, BlockNumber, Moment> {
+		pub handler: AccountId,
+		pub block: BlockNumber,
+		pub moment: Moment,
+		pub hop: u8,
+	}
+
+	pub type CustodyEntryOf<T> = CustodyEntry<
+		<T as frame_system::Config>::AccountId,
+		<T as frame_system::Config>::BlockNumber,
+		<T as pallet_timestamp::Config>::Moment,
+	>;
+
+	/// Bound on a shipment's custody log: a trip out along a route of at most `MaxSize` nodes,
+	/// redirects included, and a trip back. Returns and redirects reserve room for their whole
+	/// route up front, so a shipment on its way is never stranded by a full log.
+	pub struct MaxCustodyEntries<T>(PhantomData<T>);
+
+	impl<T: Config> Get<u32> for MaxCustodyEntries<T> {
+		fn get() -> u32 {
+			T::MaxSize::get().saturating_mul(2)
+		}
+	}
+
+	/// A handoff key encrypted to the x25519 form of the next holder's registered key:
+	/// an ephemeral public key, the encrypted key and a tag over the ciphertext.
+	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	pub struct SealedKey {
+		pub ephemeral: [u8; 32],
+		pub ciphertext: [u8; 16],
+		pub tag: [u8; 16],
+	}
+
+	/// How custody of a shipment is proven at every handoff.
+	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	pub enum HandoffMode {
+		/// The receiving node presents the key sealed into the package tag.
+		SharedKey,
+		/// The releasing and the receiving node both sign the handoff.
+		MutualSignature,
+	}
+
+	/// Proof presented by the receiving node in `update_shipment`, matching the shipment's
+	/// `HandoffMode`. Signatures are over `handoff_payload(uid, hop, at)`.
+	#[derive(Clone, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+	pub enum HandoffProof<Signature, BlockNumber> {
+		Key([u8; 16]),
+		Signatures {
+			at: BlockNumber,
+			releaser: Signature,
+			receiver: Signature,
+		},
+	}
+
+	pub type HandoffProofOf<T> = HandoffProof<<T as Config>::Signature, <T as frame_system::Config>::BlockNumber>;
+
+	/// Route vectors out of a single transit node, as submitted by the offchain worker: one route
+	/// to each of the at most `MaxSize` other transit nodes.
+	pub type RouteVectorsOf<T> = BoundedVec<
+		(
+			<T as frame_system::Config>::AccountId,
+			BoundedVec<<T as frame_system::Config>::AccountId, <T as Config>::MaxSize>,
+		),
+		<T as Config>::MaxSize,
+	>;
+
+	/// A handoff to hop `hop` of a shipment's route, signed offline by the releasing and the
+	/// receiving node over `voucher_payload(shipment_uid, hop, moment)`. `moment` is the local
+	/// time of the handoff.
+	#[derive(Clone, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+	pub struct HandoffVoucher<Signature, Moment> {
+		pub shipment_uid: u64,
+		pub hop: u8,
+		pub moment: Moment,
+		pub releaser: Signature,
+		pub receiver: Signature,
+	}
+
+	pub type HandoffVoucherOf<T> = HandoffVoucher<<T as Config>::Signature, <T as pallet_timestamp::Config>::Moment>;
+
+	/// A change of title to a shipment's goods. `block` is when `owner` became the owner.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct TitleEntry<AccountId, BlockNumber> {
+		pub owner: AccountId,
+		pub block: BlockNumber,
+	}
+
+	pub type TitleEntryOf<T> = TitleEntry<<T as frame_system::Config>::AccountId, <T as frame_system::Config>::BlockNumber>;
+
+	/// A drop-off of a multi-drop shipment: `items` are left at `node`, which is hop `hop` of
+	/// the shipment's route.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct Stop<AccountId, BlockNumber> {
+		pub node: AccountId,
+		pub items: u32,
+		pub hop: u8,
+		pub delivered_at: Option<BlockNumber>,
+	}
+
+	pub type StopOf<T> = Stop<<T as frame_system::Config>::AccountId, <T as frame_system::Config>::BlockNumber>;
+
+	/// A shipment booked by a customer, waiting for its pickup node to accept it.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[scale_info(skip_type_params(T))]
+	pub struct Booking<T: Config> {
+		pub customer: T::AccountId,
+		pub pickup_node: T::AccountId,
+		pub destination: T::AccountId,
+		pub handling: HandlingClass,
+		pub consignee: Option<T::AccountId>,
+		pub handoff_mode: HandoffMode,
+	}
+
+	/// Cash on delivery: `amount` is reserved from `buyer` and paid to `seller` once the goods
+	/// are delivered.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct GoodsPayment<AccountId, Balance> {
+		pub buyer: AccountId,
+		pub seller: AccountId,
+		pub amount: Balance,
+	}
+
+	pub type GoodsPaymentOf<T> = GoodsPayment<<T as frame_system::Config>::AccountId, BalanceOf<T>>;
+
+	/// Cover bought for a shipment. Claims are paid to the shipment's beneficial owner.
+	/// `recovered` is what was slashed from the node that lost the shipment.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct InsurancePolicy<Balance> {
+		pub declared_value: Balance,
+		pub premium: Balance,
+		pub recovered: Balance,
+	}
+
+	/// Fees a sponsor has agreed to pay for a creator: up to `limit` every `SponsorshipPeriod`
+	/// blocks, of which `spent` has been used in the period starting at `period_start`.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub struct Sponsorship<Balance, BlockNumber> {
+		pub limit: Balance,
+		pub spent: Balance,
+		pub period_start: BlockNumber,
+	}
+
+	/// What is kept of a shipment once it has been reaped.
+	#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[scale_info(skip_type_params(T))]
+	pub struct ShipmentSummary<T: Config> {
+		pub uid: u64,
+		pub creator: T::AccountId,
+		pub destination: T::AccountId,
+		pub status: ShipmentStatus,
+		pub closed_at: T::BlockNumber,
 	}
 
 	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
 	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 	pub enum ShipmentStatus {
+		/// Registered by the creator, who still holds it.
+		Created,
+		/// Ready to be picked up by the next node.
+		AwaitingPickup,
+		/// On its way to the next node.
 		InTransit,
+		/// Received by an intermediate node.
+		AtHub,
+		/// On the last leg to the destination.
+		OutForDelivery,
+		/// Held by the current holder; no handoffs until it is resumed.
+		OnHold,
+		/// At the destination, waiting for the consignee to sign for it.
+		AwaitingConfirmation,
 		Delivered,
-		Failed,
+		/// On its way back to the creator.
+		Returning,
+		Returned,
+		Cancelled,
+		Lost,
+	}
+
+	impl ShipmentStatus {
+		/// Whether the shipment has reached a final state and can be reaped.
+		pub fn is_closed(&self) -> bool {
+			matches!(
+				self,
+				ShipmentStatus::Delivered | ShipmentStatus::Returned | ShipmentStatus::Cancelled | ShipmentStatus::Lost
+			)
+		}
+
+		/// Whether the shipment can be handed over to the next node.
+		pub fn is_moving(&self) -> bool {
+			matches!(
+				self,
+				ShipmentStatus::Created |
+					ShipmentStatus::AwaitingPickup |
+					ShipmentStatus::InTransit |
+					ShipmentStatus::AtHub |
+					ShipmentStatus::OutForDelivery |
+					ShipmentStatus::Returning
+			)
+		}
+
+		/// The lifecycle of a shipment. Every status change goes through this table.
+		pub fn can_transition_to(&self, next: ShipmentStatus) -> bool {
+			use ShipmentStatus::*;
+			match (*self, next) {
+				// Operational updates and handoffs while the shipment moves
+				(
+					Created | AwaitingPickup | InTransit | AtHub | OutForDelivery | Returning,
+					AwaitingPickup | InTransit | AtHub | OutForDelivery | OnHold | AwaitingConfirmation |
+					Delivered | Returning | Returned,
+				) => true,
+				// A held shipment resumes, or is sent back
+				(OnHold, AwaitingPickup | InTransit | OutForDelivery | Returning) => true,
+				// The consignee signs for the shipment or refuses it
+				(AwaitingConfirmation, Delivered | Returning) => true,
+				// Shipments that have not left yet can be cancelled
+				(Created | AwaitingPickup | OnHold, Cancelled) => true,
+				(current, Lost) => !current.is_closed(),
+				_ => false,
+			}
+		}
+	}
+
+	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	pub enum HandlingClass {
+		General,
+		Fragile,
+		Refrigerated,
+		Hazardous,
+	}
+
+	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+	pub enum TransportMode {
+		Road,
+		Rail,
+		Sea,
+		Air,
+	}
+
+	impl TransportMode {
+		/// Cost per kilometre of the mode, in hundredths of a cost unit.
+		pub fn cost_multiplier(&self) -> u32 {
+			match self {
+				TransportMode::Road => 100,
+				TransportMode::Rail => 70,
+				TransportMode::Sea => 40,
+				TransportMode::Air => 300,
+			}
+		}
 	}
 
+	/// The current storage version.
+	pub(crate) const STORAGE_VERSION: StorageVersion = StorageVersion::new(4);
+
 	// The struct on which we build all of our Pallet logic.
 	#[pallet::pallet]
 	#[pallet::generate_store(pub(super) trait Store)]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	#[pallet::without_storage_info]
 	pub struct Pallet<T>(_);
 
     /* Placeholder for defining custom types. */
 
 	#[pallet::config]
-	pub trait Config: frame_system::Config {
+	pub trait Config: frame_system::Config + pallet_timestamp::Config + SendTransactionTypes<Call<Self>> {
 		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
-		type Currency: Currency<Self::AccountId>;
+		type Currency: ReservableCurrency<Self::AccountId>;
 		type KeyRandomNess: Randomness<Self::Hash, Self::BlockNumber>;
 		type MaxSize: Get<u32>;
+		/// Number of blocks after which edge throughput counters are reset.
+		#[pallet::constant]
+		type EraLength: Get<Self::BlockNumber>;
+		/// Number of blocks a consignee has to confirm receipt before delivery is confirmed
+		/// automatically.
+		#[pallet::constant]
+		type AutoConfirmPeriod: Get<Self::BlockNumber>;
+		/// Deposit reserved from whoever pays for a shipment until it is reaped.
+		#[pallet::constant]
+		type ShipmentDeposit: Get<BalanceOf<Self>>;
+		/// Number of blocks a closed shipment is kept before it can be reaped.
+		#[pallet::constant]
+		type RetentionPeriod: Get<Self::BlockNumber>;
+		/// Number of reaped shipment summaries kept in `ArchivedShipments`.
+		#[pallet::constant]
+		type MaxArchivedShipments: Get<u32>;
+		/// Signature transit nodes attest handoffs with.
+		type Signature: Parameter + Verify<Signer = Self::Signer>;
+		/// Identifies the account behind a `Signature`.
+		type Signer: IdentifyAccount<AccountId = Self::AccountId>;
+		/// Number of blocks handoff signatures remain valid for.
+		#[pallet::constant]
+		type HandoffValidity: Get<Self::BlockNumber>;
+		/// Maximum number of vouchers accepted by one `submit_handoffs` call.
+		#[pallet::constant]
+		type MaxHandoffBatch: Get<u32>;
+		/// Number of blocks after which an unused handoff key is revoked. Zero disables expiry.
+		#[pallet::constant]
+		type KeyLifetime: Get<Self::BlockNumber>;
+		/// Fee charged per unit of route cost a shipment travels. Zero disables fees.
+		#[pallet::constant]
+		type FeePerCostUnit: Get<BalanceOf<Self>>;
+		/// Non-fungible items mirroring shipments. `()` disables the integration.
+		type ShipmentItems: ShipmentItems<Self::AccountId>;
+		/// Identifies the insurance pool account premiums are paid into.
+		#[pallet::constant]
+		type InsurancePalletId: Get<PalletId>;
+		/// Share of a shipment's declared value charged as insurance premium.
+		#[pallet::constant]
+		type InsurancePremium: Get<Permill>;
+		/// Share of the rest of the insurance pool a claim may take on top of what was recovered
+		/// from the node that lost the shipment.
+		#[pallet::constant]
+		type MaxClaimShare: Get<Permill>;
+		/// Receives the treasury's share of shipment fees. The rest pays the nodes that carry the
+		/// shipment.
+		type FeeDestination: OnUnbalanced<NegativeImbalanceOf<Self>>;
+		/// Share of every shipment fee that goes to `FeeDestination`.
+		#[pallet::constant]
+		type TreasuryShare: Get<Permill>;
+		/// Number of blocks after which sponsorship allowances are renewed.
+		#[pallet::constant]
+		type SponsorshipPeriod: Get<Self::BlockNumber>;
+		/// Number of handoffs per era a transit node can submit without transaction fees.
+		#[pallet::constant]
+		type MaxFeelessHandoffs: Get<u32>;
 	}
 
 	#[pallet::event]
@@ -72,6 +429,45 @@
 		TransitPointCreated(T::AccountId),
 		TransitPointRemoved(T::AccountId),
 		NeighbourUpdated(T::AccountId,T::AccountId),
+		NodeLocationUpdated(T::AccountId),
+		NodeCapabilitiesUpdated(T::AccountId),
+		NodeCapacityUpdated(T::AccountId),
+		EdgeCapacityUpdated(T::AccountId,T::AccountId),
+		RouteVectorsUpdated(u32),
+		ShipmentAwaitingConfirmation(u64),
+		ShipmentConfirmed(u64),
+		ShipmentAutoConfirmed(u64),
+		ShipmentReaped(u64),
+		HandoffKeyRegistered(T::AccountId),
+		HandoffVouchersApplied(u32),
+		HandoffKeyReissued(u64),
+		HandoffKeyExpired(u64),
+		ShipmentReturning(u64),
+		ShipmentReturned(u64),
+		ShipmentStatusChanged(u64, ShipmentStatus, ShipmentStatus),
+		ShipmentRedirected(u64, T::AccountId),
+		StopDelivered(u64, T::AccountId),
+		ShipmentBooked(u64, T::AccountId),
+		BookingAccepted(u64, u64),
+		BookingCancelled(u64),
+		TitleTransferProposed(u64, T::AccountId),
+		TitleTransferred(u64, T::AccountId),
+		PaymentReleased(u64, T::AccountId, BalanceOf<T>),
+		PaymentRefunded(u64, T::AccountId, BalanceOf<T>),
+		ShipmentInsured(u64, BalanceOf<T>),
+		ClaimPaid(u64, T::AccountId, BalanceOf<T>),
+		NodeBonded(T::AccountId, BalanceOf<T>),
+		NodeUnbonded(T::AccountId, BalanceOf<T>),
+		NodeSlashed(T::AccountId, BalanceOf<T>),
+		/// A shipment fee was paid: payer, fee, share of it that went to the treasury.
+		FeeCharged(T::AccountId, BalanceOf<T>, BalanceOf<T>),
+		FeeRefunded(T::AccountId, BalanceOf<T>),
+		SponsorshipApproved(T::AccountId, T::AccountId, BalanceOf<T>),
+		SponsorshipRevoked(T::AccountId, T::AccountId),
+		ShipmentSponsored(u64, T::AccountId),
+		HandoffKeyRotated(u64),
+		/// A travelled leg was paid for: shipment, node that carried it, amount.
+		LegFeePaid(u64, T::AccountId, BalanceOf<T>),
 		ShipmentCreated(T::AccountId),
 		ShipmentUpdated(T::AccountId),
 		ShipmentReceived(T::AccountId),
@@ -92,7 +488,43 @@
 		TransitPointNotFound,
 		UIDNotFound,
 		UnauthorizedCaller,
-		CallerIsNotFirstNode
+		CallerIsNotFirstNode,
+		InvalidCoordinates,
+		LocationNotFound,
+		NoCompliantRoute,
+		RouteCapacityExhausted,
+		StaleRouteVectors,
+		RouteEdgesUnderstated,
+		KeyCommitmentRequired,
+		SealedKeyRequired,
+		ShipmentNotAwaitingConfirmation,
+		CustodyLogFull,
+		ShipmentNotClosed,
+		RetentionPeriodNotElapsed,
+		InvalidHandoffProof,
+		InvalidSignature,
+		HandoffExpired,
+		VoucherOutOfOrder,
+		VoucherAlreadyUsed,
+		NoHandoffKey,
+		ShipmentNotReturnable,
+		InvalidStatusTransition,
+		ShipmentNotMoving,
+		ShipmentNotRedirectable,
+		BookingNotFound,
+		ShipmentAlreadyClosed,
+		NoPendingTitleTransfer,
+		TitleHistoryFull,
+		ShipmentNotInsurable,
+		AlreadyInsured,
+		NotInsured,
+		ShipmentNotLost,
+		InsufficientBond,
+		NodeHoldingShipments,
+		NoSponsorship,
+		SponsorshipExceeded,
+		NoPrecomputedRoute,
+		ShipmentNotClaimable,
 	}
 
 	#[pallet::storage]
@@ -127,14 +559,136 @@
 		OptionQuery,
 	>;
 
-	// shipment_uid -> key map
+	// Bumped whenever nodes or edge costs change, so that precomputed routes can be refreshed
+	#[pallet::storage]
+	#[pallet::getter(fn topology_version)]
+	pub(super) type TopologyVersion<T:Config> = StorageValue<
+		_,
+		u32,
+		ValueQuery,
+	>;
+
+	// Topology version `RouteVector` was computed for
+	#[pallet::storage]
+	#[pallet::getter(fn route_vector_version)]
+	pub(super) type RouteVectorVersion<T:Config> = StorageValue<
+		_,
+		u32,
+		ValueQuery,
+	>;
+
+	// Topology version the routes out of each transit node were last submitted for
+	#[pallet::storage]
+	#[pallet::getter(fn route_vector_source_version)]
+	pub(super) type RouteVectorSources<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		u32,
+		OptionQuery,
+	>;
+
+	// Topology version being submitted, and the number of transit nodes whose routes are in.
+	// `RouteVectorVersion` moves to the new version once every transit node's routes are in.
+	#[pallet::storage]
+	pub(super) type RouteVectorProgress<T:Config> = StorageValue<
+		_,
+		(u32, u32),
+		ValueQuery,
+	>;
+
+	// Edges listed here have their cost derived from the distance between their endpoints.
+	// Setting a cost through `update_neighbour` removes the edge from this map.
+	#[pallet::storage]
+	#[pallet::getter(fn edge_mode)]
+	pub(super) type EdgeModes<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		T::AccountId,
+		TransportMode,
+		OptionQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn node_location)]
+	pub(super) type NodeLocations<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Coordinates,
+		OptionQuery,
+	>;
+
+	// Handling classes a node declares it can take, on top of `HandlingClass::General`
+	#[pallet::storage]
+	#[pallet::getter(fn node_capabilities)]
+	pub(super) type NodeCapabilities<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		BoundedVec<HandlingClass, T::MaxSize>,
+		ValueQuery,
+	>;
+
+	// Maximum number of shipments a node may hold at once. Unlimited when absent.
+	#[pallet::storage]
+	#[pallet::getter(fn node_capacity)]
+	pub(super) type NodeCapacity<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		u32,
+		OptionQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn node_holdings)]
+	pub(super) type NodeHoldings<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		u32,
+		ValueQuery,
+	>;
+
+	// Maximum number of shipments dispatched over an edge per era. Unlimited when absent.
+	#[pallet::storage]
+	#[pallet::getter(fn edge_capacity)]
+	pub(super) type EdgeCapacity<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		T::AccountId,
+		u32,
+		OptionQuery,
+	>;
+
+	// from, to -> start of the era and number of shipments dispatched over the edge in that era.
+	// Counts of past eras read as zero, so nothing has to be cleared when an era ends.
+	#[pallet::storage]
+	pub(super) type EdgeThroughput<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		T::AccountId,
+		(T::BlockNumber, u32),
+		ValueQuery,
+	>;
+
+	// shipment_uid -> hash of the key expected from the next holder. The key itself never
+	// touches the chain: the node releasing the shipment generates it and seals it into the
+	// package tag, where the receiving node scans it.
 	#[pallet::storage]
 	#[pallet::getter(fn shipment_uid_to_key)]
 	pub(super) type UIDToKey<T:Config> = StorageMap<
 		_,
 		Blake2_128Concat,
 		u64,
-		[u8; 16],
+		T::Hash,
 		OptionQuery,
 	>;
 
@@ -149,163 +703,1783 @@
 		OptionQuery,
 	>;
 
+	// shipment_uid -> block at which its current handoff key expires
 	#[pallet::storage]
-	#[pallet::getter(fn shipment_uid)]
-	pub(super) type ShipmentUID<T:Config> = StorageValue<
+	#[pallet::getter(fn key_expires_at)]
+	pub(super) type KeyExpiresAt<T:Config> = StorageMap<
 		_,
+		Blake2_128Concat,
 		u64,
-		ValueQuery,
+		T::BlockNumber,
+		OptionQuery,
 	>;
 
+	// block number -> shipments whose handoff key expires at that block
 	#[pallet::storage]
-	#[pallet::getter(fn transit_nodes)]
-	pub(super) type TransitNodes<T:Config> = StorageValue<
+	#[pallet::getter(fn key_expiries)]
+	pub(super) type KeyExpiries<T:Config> = StorageMap<
 		_,
-		Vec<T::AccountId>,
+		Twox64Concat,
+		T::BlockNumber,
+		Vec<u64>,
 		ValueQuery,
 	>;
 
+	// transit node -> public key handoff keys for it are sealed to
 	#[pallet::storage]
-	pub(super) type Nonce<T:Config> = StorageValue<
+	#[pallet::getter(fn handoff_key)]
+	pub(super) type HandoffKeys<T:Config> = StorageMap<
 		_,
-		u32,
-		ValueQuery,
+		Blake2_128Concat,
+		T::AccountId,
+		crypto::Public,
+		OptionQuery,
 	>;
 
+	// shipment_uid -> current handoff key, sealed to the next holder
+	#[pallet::storage]
+	#[pallet::getter(fn sealed_key)]
+	pub(super) type SealedKeys<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		SealedKey,
+		OptionQuery,
+	>;
 
-    #[pallet::call]
-    impl<T: Config> Pallet<T> {
+	// shipment_uid -> every handoff of the shipment, oldest first. Never rewritten.
+	#[pallet::storage]
+	#[pallet::getter(fn custody_log)]
+	pub(super) type CustodyLog<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		BoundedVec<CustodyEntryOf<T>, MaxCustodyEntries<T>>,
+		ValueQuery,
+	>;
 
-		#[pallet::weight(0)]
-		pub fn create_new_transit_node(
-			origin: OriginFor<T>,
-			transit_node: T::AccountId,
-			neighbours: BoundedVec<(T::AccountId, u32), T::MaxSize>
-		) -> DispatchResult {
+	// shipment_uid -> every owner of the shipment's goods, oldest first
+	#[pallet::storage]
+	#[pallet::getter(fn title_history)]
+	pub(super) type TitleHistory<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		BoundedVec<TitleEntryOf<T>, T::MaxSize>,
+		ValueQuery,
+	>;
 
-			ensure_root(origin)?;
-			ensure!(!Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointAlreadyExists);
-			ensure!(
-				neighbours.iter().all(|neighbour| neighbour.0 != transit_node && Self::transit_nodes().contains(&neighbour.0)),
-				Error::<T>::InvalidRoute);
+	// shipment_uid -> account the title is being transferred to, until it accepts
+	#[pallet::storage]
+	#[pallet::getter(fn pending_title_transfer)]
+	pub(super) type PendingTitleTransfers<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		T::AccountId,
+		OptionQuery,
+	>;
 
-			for neighbour in neighbours.iter() {
-				RouteCosts::<T>::insert(transit_node.clone(), neighbour.0.clone(), neighbour.1);
-				RouteCosts::<T>::insert(neighbour.0.clone(), transit_node.clone(), neighbour.1);
-			}
+	// shipment_uid, voucher payload hash -> block the voucher was applied at
+	#[pallet::storage]
+	#[pallet::getter(fn consumed_voucher)]
+	pub(super) type ConsumedVouchers<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		Identity,
+		T::Hash,
+		T::BlockNumber,
+		OptionQuery,
+	>;
 
-			TransitNodes::<T>::append(transit_node.clone());
-			let transit_point_counts = Self::count_for_transit_point().checked_add(1).ok_or(ArithmeticError::Overflow)?;
-			CountForTransitPoints::<T>::put(transit_point_counts);
+	// shipment_uid -> drop-offs of a multi-drop shipment, in route order
+	#[pallet::storage]
+	#[pallet::getter(fn shipment_stops)]
+	pub(super) type ShipmentStops<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		BoundedVec<StopOf<T>, T::MaxSize>,
+		OptionQuery,
+	>;
 
-			Self::deposit_event(Event::TransitPointCreated(transit_node));
+	// shipment_uid -> account the deposit was reserved from and its amount
+	#[pallet::storage]
+	#[pallet::getter(fn shipment_deposit)]
+	pub(super) type ShipmentDeposits<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		(T::AccountId, BalanceOf<T>),
+		OptionQuery,
+	>;
 
-			Ok(())
-		}
+	// shipment_uid -> block at which the shipment was delivered or failed
+	#[pallet::storage]
+	#[pallet::getter(fn shipment_closed_at)]
+	pub(super) type ShipmentClosedAt<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		T::BlockNumber,
+		OptionQuery,
+	>;
 
-		#[pallet::weight(0)]
-		pub fn update_neighbour(
-			origin: OriginFor<T>,
-			node1: T::AccountId,
-			node2: T::AccountId,
+	// Summaries of the most recently reaped shipments, oldest first
+	#[pallet::storage]
+	#[pallet::getter(fn archived_shipments)]
+	pub(super) type ArchivedShipments<T:Config> = StorageValue<
+		_,
+		BoundedVec<ShipmentSummary<T>, T::MaxArchivedShipments>,
+		ValueQuery,
+	>;
+
+	// booking_uid -> shipment booked by a customer, until the pickup node accepts it
+	#[pallet::storage]
+	#[pallet::getter(fn bookings)]
+	pub(super) type Bookings<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		Booking<T>,
+		OptionQuery,
+	>;
+
+	// booking_uid -> cash on delivery reserved when the shipment was booked
+	#[pallet::storage]
+	#[pallet::getter(fn booking_payment)]
+	pub(super) type BookingPayments<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		GoodsPaymentOf<T>,
+		OptionQuery,
+	>;
+
+	// shipment_uid -> cash on delivery held until the shipment is closed
+	#[pallet::storage]
+	#[pallet::getter(fn goods_payment)]
+	pub(super) type GoodsPayments<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		GoodsPaymentOf<T>,
+		OptionQuery,
+	>;
+
+	// shipment_uid -> insurance bought for the shipment, until it is claimed
+	#[pallet::storage]
+	#[pallet::getter(fn policy)]
+	pub(super) type Policies<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		InsurancePolicy<BalanceOf<T>>,
+		OptionQuery,
+	>;
+
+	// transit node -> bond reserved from it to cover shipments lost in its custody
+	#[pallet::storage]
+	#[pallet::getter(fn node_bond)]
+	pub(super) type NodeBonds<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		BalanceOf<T>,
+		ValueQuery,
+	>;
+
+	// sponsor, creator -> fees the sponsor pays for shipments of the creator
+	#[pallet::storage]
+	#[pallet::getter(fn sponsorship)]
+	pub(super) type Sponsorships<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		T::AccountId,
+		Sponsorship<BalanceOf<T>, T::BlockNumber>,
+		OptionQuery,
+	>;
+
+	// shipment_uid -> sponsor that paid for the shipment
+	#[pallet::storage]
+	#[pallet::getter(fn shipment_sponsor)]
+	pub(super) type ShipmentSponsors<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		T::AccountId,
+		OptionQuery,
+	>;
+
+	// shipment_uid, hop a leg of the route leads to -> account that paid for the leg and the
+	// part of its fee still held from that account. Legs are paid out once travelled.
+	#[pallet::storage]
+	#[pallet::getter(fn leg_fee)]
+	pub(super) type LegFees<T:Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		u64,
+		Twox64Concat,
+		u8,
+		(T::AccountId, BalanceOf<T>),
+		OptionQuery,
+	>;
+
+	// transit node -> start of the era and number of feeless handoffs it made in that era
+	#[pallet::storage]
+	#[pallet::getter(fn feeless_handoffs)]
+	pub(super) type FeelessHandoffs<T:Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		(T::BlockNumber, u32),
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn booking_uid)]
+	pub(super) type BookingUID<T:Config> = StorageValue<
+		_,
+		u64,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn shipment_uid)]
+	pub(super) type ShipmentUID<T:Config> = StorageValue<
+		_,
+		u64,
+		ValueQuery,
+	>;
+
+	// block number -> shipments to confirm automatically at that block
+	#[pallet::storage]
+	#[pallet::getter(fn confirmation_deadlines)]
+	pub(super) type ConfirmationDeadlines<T:Config> = StorageMap<
+		_,
+		Twox64Concat,
+		T::BlockNumber,
+		Vec<u64>,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn transit_nodes)]
+	pub(super) type TransitNodes<T:Config> = StorageValue<
+		_,
+		Vec<T::AccountId>,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	pub(super) type Nonce<T:Config> = StorageValue<
+		_,
+		u32,
+		ValueQuery,
+	>;
+
+	#[pallet::genesis_config]
+	#[derive(Default)]
+	pub struct GenesisConfig {}
+
+	#[pallet::genesis_build]
+	impl<T: Config> GenesisBuild<T> for GenesisConfig {
+		fn build(&self) {
+			// Set up before anyone can claim the collection shipment items are kept in
+			T::ShipmentItems::setup().expect("shipment items can be set up at genesis");
+		}
+	}
+
+
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_runtime_upgrade() -> Weight {
+			crate::migrations::v1::migrate::<T>()
+				.saturating_add(crate::migrations::v2::migrate::<T>())
+				.saturating_add(crate::migrations::v3::migrate::<T>())
+				.saturating_add(crate::migrations::v4::migrate::<T>())
+		}
+
+		fn on_initialize(now: T::BlockNumber) -> Weight {
+			let mut weight: Weight = 0;
+
+			// Shipments the consignee did not confirm in time are delivered automatically
+			weight = weight.saturating_add(T::DbWeight::get().reads_writes(1, 1));
+			for shipment_uid in ConfirmationDeadlines::<T>::take(now) {
+				weight = weight.saturating_add(T::DbWeight::get().reads(1));
+				if let Some(mut shipment) = Self::uid_to_shipment(shipment_uid) {
+					if shipment.status == ShipmentStatus::AwaitingConfirmation &&
+						Self::transition(&mut shipment, ShipmentStatus::Delivered).is_ok()
+					{
+						UIDToShipment::<T>::insert(shipment_uid, &shipment);
+						weight = weight.saturating_add(T::DbWeight::get().reads_writes(1, 5));
+						Self::deposit_event(Event::ShipmentAutoConfirmed(shipment_uid));
+					}
+				}
+			}
+
+			// Keys that were not used in time are replaced with a fresh key sealed to the next
+			// holder. Holders without a registered handoff key can't be sent one, so their key
+			// is revoked until the creator reissues it. Entries for keys that were used or
+			// reissued since are skipped.
+			weight = weight.saturating_add(T::DbWeight::get().reads_writes(1, 1));
+			for shipment_uid in KeyExpiries::<T>::take(now) {
+				weight = weight.saturating_add(T::DbWeight::get().reads(1));
+				if Self::key_expires_at(shipment_uid) == Some(now) {
+					weight = weight.saturating_add(T::DbWeight::get().reads(3));
+					if Self::rotate_handoff_key(shipment_uid) {
+						weight = weight.saturating_add(T::DbWeight::get().writes(6));
+						Self::deposit_event(Event::HandoffKeyRotated(shipment_uid));
+					} else {
+						Self::revoke_handoff_key(shipment_uid);
+						weight = weight.saturating_add(T::DbWeight::get().writes(3));
+						Self::deposit_event(Event::HandoffKeyExpired(shipment_uid));
+					}
+				}
+			}
+
+			weight
+		}
+
+		fn offchain_worker(now: T::BlockNumber) {
+			let topology_version = Self::topology_version();
+			if Self::route_vector_version() == topology_version {
+				return
+			}
+
+			// Don't resubmit while a transaction for this version may still be in the pool
+			let submitted = StorageValueRef::persistent(b"asset_tracking::route_vectors");
+			if let Ok(Some((version, at))) = submitted.get::<(u32, T::BlockNumber)>() {
+				if version == topology_version && now < at + ROUTE_VECTOR_RESUBMIT_BLOCKS.into() {
+					return
+				}
+			}
+
+			// Routes are submitted per source node, skipping nodes whose routes are in already
+			for (source, routes) in Self::compute_route_vectors() {
+				if Self::route_vector_source_version(&source) == Some(topology_version) {
+					continue;
+				}
+				let edges = Self::route_vector_edges(&source, &routes);
+				let call = Call::submit_route_vectors { topology_version, source, routes, edges };
+				let _ = SubmitTransaction::<T, Call<T>>::submit_unsigned_transaction(call.into());
+			}
+			submitted.set(&(topology_version, now));
+		}
+	}
+
+	#[pallet::validate_unsigned]
+	impl<T: Config> ValidateUnsigned for Pallet<T> {
+		type Call = Call<T>;
+
+		fn validate_unsigned(source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+			// Route vectors are only accepted from the local offchain worker or inside a block
+			if !matches!(source, TransactionSource::Local | TransactionSource::InBlock) {
+				return InvalidTransaction::Call.into()
+			}
+
+			match call {
+				Call::submit_route_vectors { topology_version, source, .. } => {
+					if *topology_version != Self::topology_version() ||
+						Self::route_vector_source_version(source) == Some(*topology_version)
+					{
+						return InvalidTransaction::Stale.into()
+					}
+
+					ValidTransaction::with_tag_prefix("AssetTrackingRouteVectors")
+						.priority(TransactionPriority::max_value() / 2)
+						.and_provides((topology_version, source))
+						.longevity(ROUTE_VECTOR_RESUBMIT_BLOCKS.into())
+						.propagate(false)
+						.build()
+				},
+				_ => InvalidTransaction::Call.into(),
+			}
+		}
+	}
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, (neighbours.len() as u64).saturating_mul(2).saturating_add(3)))]
+		pub fn create_new_transit_node(
+			origin: OriginFor<T>,
+			transit_node: T::AccountId,
+			neighbours: BoundedVec<(T::AccountId, u32), T::MaxSize>
+		) -> DispatchResult {
+
+			ensure_root(origin)?;
+			ensure!(!Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointAlreadyExists);
+			ensure!(Self::count_for_transit_point() < T::MaxSize::get() as u64, Error::<T>::TransitNodesOverFlow);
+			ensure!(
+				neighbours.iter().all(|neighbour| neighbour.0 != transit_node && Self::transit_nodes().contains(&neighbour.0)),
+				Error::<T>::InvalidRoute);
+
+			for neighbour in neighbours.iter() {
+				RouteCosts::<T>::insert(transit_node.clone(), neighbour.0.clone(), neighbour.1);
+				RouteCosts::<T>::insert(neighbour.0.clone(), transit_node.clone(), neighbour.1);
+			}
+
+			TransitNodes::<T>::append(transit_node.clone());
+			let transit_point_counts = Self::count_for_transit_point().checked_add(1).ok_or(ArithmeticError::Overflow)?;
+			CountForTransitPoints::<T>::put(transit_point_counts);
+			Self::bump_topology_version();
+
+			Self::deposit_event(Event::TransitPointCreated(transit_node));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(2, 5))]
+		pub fn update_neighbour(
+			origin: OriginFor<T>,
+			node1: T::AccountId,
+			node2: T::AccountId,
 			cost: u32
 		) ->DispatchResult {
 			ensure_root(origin)?;
 			ensure!(Self::transit_nodes().contains(&node1) && Self::transit_nodes().contains(&node2), Error::<T>::TransitPointNotFound);
 
-			RouteCosts::<T>::insert(node1.clone(),node2.clone(),cost.clone());
-			RouteCosts::<T>::insert(node2.clone(),node1.clone(),cost.clone());
+			RouteCosts::<T>::insert(node1.clone(),node2.clone(),cost.clone());
+			RouteCosts::<T>::insert(node2.clone(),node1.clone(),cost.clone());
+
+			// A hand-typed cost overrides any distance-derived one
+			EdgeModes::<T>::remove(&node1, &node2);
+			EdgeModes::<T>::remove(&node2, &node1);
+			Self::bump_topology_version();
+
+			Self::deposit_event(Event::NeighbourUpdated(node1,node2));
+
+			Ok(())
+		}
+
+		// Every edge of the node with a transport mode is rederived, at most `MaxSize` of them
+		#[pallet::weight({
+			let edges = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads_writes(edges.saturating_mul(2).saturating_add(3), edges.saturating_mul(2).saturating_add(2))
+		})]
+		pub fn set_node_location(
+			origin: OriginFor<T>,
+			transit_node: T::AccountId,
+			location: Coordinates
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+			ensure!(location.is_valid(), Error::<T>::InvalidCoordinates);
+
+			NodeLocations::<T>::insert(&transit_node, location);
+
+			for (neighbour, mode) in EdgeModes::<T>::iter_prefix(&transit_node) {
+				if let Some(cost) = Self::derive_edge_cost(&transit_node, &neighbour, mode) {
+					RouteCosts::<T>::insert(transit_node.clone(), neighbour.clone(), cost);
+					RouteCosts::<T>::insert(neighbour.clone(), transit_node.clone(), cost);
+				}
+			}
+			Self::bump_topology_version();
+
+			Self::deposit_event(Event::NodeLocationUpdated(transit_node));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(4, 5))]
+		pub fn set_edge_mode(
+			origin: OriginFor<T>,
+			node1: T::AccountId,
+			node2: T::AccountId,
+			mode: TransportMode
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&node1) && Self::transit_nodes().contains(&node2), Error::<T>::TransitPointNotFound);
+			ensure!(node1 != node2, Error::<T>::InvalidRoute);
+
+			let cost = Self::derive_edge_cost(&node1, &node2, mode).ok_or(Error::<T>::LocationNotFound)?;
+
+			EdgeModes::<T>::insert(node1.clone(), node2.clone(), mode);
+			EdgeModes::<T>::insert(node2.clone(), node1.clone(), mode);
+			RouteCosts::<T>::insert(node1.clone(), node2.clone(), cost);
+			RouteCosts::<T>::insert(node2.clone(), node1.clone(), cost);
+			Self::bump_topology_version();
+
+			Self::deposit_event(Event::NeighbourUpdated(node1,node2));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(1, 1))]
+		pub fn set_node_capabilities(
+			origin: OriginFor<T>,
+			transit_node: T::AccountId,
+			capabilities: BoundedVec<HandlingClass, T::MaxSize>
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+
+			NodeCapabilities::<T>::insert(&transit_node, capabilities);
+
+			Self::deposit_event(Event::NodeCapabilitiesUpdated(transit_node));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(1, 1))]
+		pub fn set_node_capacity(
+			origin: OriginFor<T>,
+			transit_node: T::AccountId,
+			max_holdings: Option<u32>
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+
+			NodeCapacity::<T>::set(&transit_node, max_holdings);
+
+			Self::deposit_event(Event::NodeCapacityUpdated(transit_node));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(1, 2))]
+		pub fn set_edge_capacity(
+			origin: OriginFor<T>,
+			node1: T::AccountId,
+			node2: T::AccountId,
+			max_per_era: Option<u32>
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&node1) && Self::transit_nodes().contains(&node2), Error::<T>::TransitPointNotFound);
+
+			EdgeCapacity::<T>::set(&node1, &node2, max_per_era);
+			EdgeCapacity::<T>::set(&node2, &node1, max_per_era);
+
+			Self::deposit_event(Event::EdgeCapacityUpdated(node1,node2));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(1, 1))]
+		pub fn register_handoff_key(origin: OriginFor<T>, public: crypto::Public) -> DispatchResult {
+			let transit_node = ensure_signed(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+
+			HandoffKeys::<T>::insert(&transit_node, public);
+
+			Self::deposit_event(Event::HandoffKeyRegistered(transit_node));
+
+			Ok(())
+		}
+
+		// Up to `MaxSize` edges, routes, modes and capacities in each direction are removed
+		#[pallet::weight({
+			let nodes = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads_writes(nodes.saturating_add(3), nodes.saturating_mul(7).saturating_add(8))
+		})]
+		pub fn remove_transit_node(origin: OriginFor<T>, transit_node: T::AccountId) -> DispatchResult {
+
+			ensure_root(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+
+			RouteCosts::<T>::remove_prefix(&transit_node, None);
+			RouteVector::<T>::remove_prefix(&transit_node, None);
+			RouteVectorSources::<T>::remove(&transit_node);
+			EdgeModes::<T>::remove_prefix(&transit_node, None);
+			NodeLocations::<T>::remove(&transit_node);
+			NodeCapabilities::<T>::remove(&transit_node);
+			NodeCapacity::<T>::remove(&transit_node);
+			EdgeCapacity::<T>::remove_prefix(&transit_node, None);
+			for node in Self::transit_nodes() {
+				if node == transit_node {
+					continue;
+				}
+				if RouteCosts::<T>::contains_key(&node, &transit_node) {
+					RouteCosts::<T>::remove(&node, &transit_node);
+				}
+				EdgeModes::<T>::remove(&node, &transit_node);
+				EdgeCapacity::<T>::remove(&node, &transit_node);
+			}
+
+			let transit_point_counts = Self::count_for_transit_point().checked_sub(1).ok_or(ArithmeticError::Underflow)?;
+			let mut new_transit_nodes = Self::transit_nodes();
+			new_transit_nodes.retain(|nodes| *nodes != transit_node);
+
+			CountForTransitPoints::<T>::put(transit_point_counts);
+			TransitNodes::<T>::put(new_transit_nodes);
+			Self::bump_topology_version();
+
+			Self::deposit_event(Event::TransitPointRemoved(transit_node));
+
+			Ok(())
+		}
+
+		// Every leg of every path is read, as are the `edges` edges out of `source` and out of
+		// the routes' ends. The previous routes out of `source` are at most `MaxSize`.
+		#[pallet::weight({
+			let legs = routes.iter().map(|(_, path)| path.len() as u64).sum::<u64>();
+			T::DbWeight::get().reads_writes(
+				legs.saturating_add(*edges as u64).saturating_add(5),
+				(T::MaxSize::get() as u64).saturating_add(routes.len() as u64).saturating_add(3),
+			)
+		})]
+		pub fn submit_route_vectors(
+			origin: OriginFor<T>,
+			topology_version: u32,
+			source: T::AccountId,
+			routes: RouteVectorsOf<T>,
+			edges: u32,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			ensure!(topology_version == Self::topology_version(), Error::<T>::StaleRouteVectors);
+			ensure!(Self::route_vector_source_version(&source) != Some(topology_version),
+				Error::<T>::StaleRouteVectors);
+			ensure!(Self::transit_nodes().contains(&source), Error::<T>::TransitPointNotFound);
+			Self::check_shortest_paths(&source, &routes, edges)?;
+
+			RouteVector::<T>::remove_prefix(&source, None);
+			for (to, path) in routes {
+				RouteVector::<T>::insert(&source, to, path.into_inner());
+			}
+			RouteVectorSources::<T>::insert(&source, topology_version);
+
+			let submitted = RouteVectorProgress::<T>::mutate(|(version, submitted)| {
+				if *version != topology_version {
+					*version = topology_version;
+					*submitted = 0;
+				}
+				*submitted = submitted.saturating_add(1);
+				*submitted
+			});
+			if submitted as u64 >= Self::count_for_transit_point() {
+				RouteVectorVersion::<T>::put(topology_version);
+				Self::deposit_event(Event::RouteVectorsUpdated(topology_version));
+			}
+
+			Ok(())
+		}
+
+		#[pallet::weight(
+			Pallet::<T>::find_route_weight()
+			.saturating_add(Pallet::<T>::create_shipment_weight())
+			.saturating_add(T::DbWeight::get().reads_writes(1, 2))
+		)]
+		#[transactional]
+		pub fn create_shipment(
+			origin: OriginFor<T>,
+			destination: T::AccountId,
+			handling: HandlingClass,
+			consignee: Option<T::AccountId>,
+			handoff_mode: HandoffMode,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>,
+			sponsor: Option<T::AccountId>
+		) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+
+			let route = Self::find_route(transit_node.clone(),destination.clone(),handling)?;
+			let payer = match sponsor {
+				Some(sponsor) => {
+					// The deposit is held from the sponsor as well, so it counts against the allowance
+					let cost = Self::route_fee(&route)?.saturating_add(T::ShipmentDeposit::get());
+					Self::spend_allowance(&sponsor, &transit_node, cost)?;
+					sponsor
+				},
+				None => transit_node.clone(),
+			};
+			let shipment_uid = Self::do_create_shipment(
+				transit_node.clone(),
+				transit_node.clone(),
+				payer.clone(),
+				route,
+				handling,
+				consignee,
+				handoff_mode,
+				key_commitment,
+				sealed_key)?;
+			if payer != transit_node {
+				ShipmentSponsors::<T>::insert(shipment_uid, &payer);
+				Self::deposit_event(Event::ShipmentSponsored(shipment_uid, payer));
+			}
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().writes(1))]
+		pub fn approve_sponsorship(
+			origin: OriginFor<T>,
+			creator: T::AccountId,
+			limit: BalanceOf<T>
+		) -> DispatchResult {
+
+			let sponsor = ensure_signed(origin)?;
+			Sponsorships::<T>::insert(&sponsor, &creator, Sponsorship {
+				limit,
+				spent: Zero::zero(),
+				period_start: <frame_system::Pallet<T>>::block_number(),
+			});
+
+			Self::deposit_event(Event::SponsorshipApproved(sponsor, creator, limit));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(1, 1))]
+		pub fn revoke_sponsorship(origin: OriginFor<T>, creator: T::AccountId) -> DispatchResult {
+
+			let sponsor = ensure_signed(origin)?;
+			ensure!(Sponsorships::<T>::contains_key(&sponsor, &creator), Error::<T>::NoSponsorship);
+			Sponsorships::<T>::remove(&sponsor, &creator);
+
+			Self::deposit_event(Event::SponsorshipRevoked(sponsor, creator));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, 4))]
+		pub fn book_shipment(
+			origin: OriginFor<T>,
+			pickup_node: T::AccountId,
+			destination: T::AccountId,
+			handling: HandlingClass,
+			consignee: Option<T::AccountId>,
+			handoff_mode: HandoffMode,
+			payment: Option<(T::AccountId, BalanceOf<T>)>
+		) -> DispatchResult {
+
+			let customer = ensure_signed(origin)?;
+			let transit_nodes = Self::transit_nodes();
+			ensure!(
+				transit_nodes.contains(&pickup_node) && transit_nodes.contains(&destination),
+				Error::<T>::TransitPointNotFound);
+			ensure!(pickup_node != destination, Error::<T>::InvalidRoute);
+
+			let booking_uid = Self::booking_uid().checked_add(1).ok_or(ArithmeticError::Overflow)?;
+			// The customer buys the goods from `seller` and pays for them on delivery
+			if let Some((seller, amount)) = payment {
+				ensure!(seller != customer, Error::<T>::UnauthorizedCaller);
+				T::Currency::reserve(&customer, amount)?;
+				BookingPayments::<T>::insert(booking_uid, GoodsPayment { buyer: customer.clone(), seller, amount });
+			}
+			Bookings::<T>::insert(booking_uid, Booking {
+				customer,
+				pickup_node: pickup_node.clone(),
+				destination,
+				handling,
+				consignee,
+				handoff_mode,
+			});
+			BookingUID::<T>::put(booking_uid);
+
+			Self::deposit_event(Event::ShipmentBooked(booking_uid, pickup_node));
+
+			Ok(())
+		}
+
+		#[pallet::weight(
+			Pallet::<T>::find_route_weight()
+			.saturating_add(Pallet::<T>::create_shipment_weight())
+			.saturating_add(T::DbWeight::get().reads_writes(2, 3))
+		)]
+		#[transactional]
+		pub fn accept_booking(
+			origin: OriginFor<T>,
+			booking_uid: u64,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+			let booking = Self::bookings(booking_uid).ok_or(Error::<T>::BookingNotFound)?;
+			ensure!(transit_node == booking.pickup_node, Error::<T>::UnauthorizedCaller);
+
+			// The route is only fixed now, from the node that will actually pick the shipment up
+			let route = Self::find_route(transit_node.clone(), booking.destination.clone(), booking.handling)?;
+			let shipment_uid = Self::do_create_shipment(
+				transit_node,
+				booking.customer.clone(),
+				booking.customer,
+				route,
+				booking.handling,
+				booking.consignee,
+				booking.handoff_mode,
+				key_commitment,
+				sealed_key)?;
+			Bookings::<T>::remove(booking_uid);
+			if let Some(payment) = BookingPayments::<T>::take(booking_uid) {
+				GoodsPayments::<T>::insert(shipment_uid, payment);
+			}
+
+			Self::deposit_event(Event::BookingAccepted(booking_uid, shipment_uid));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, 3))]
+		pub fn cancel_booking(origin: OriginFor<T>, booking_uid: u64) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			let booking = Self::bookings(booking_uid).ok_or(Error::<T>::BookingNotFound)?;
+			// The customer can withdraw the booking and the pickup node can decline it
+			ensure!(caller == booking.customer || caller == booking.pickup_node, Error::<T>::UnauthorizedCaller);
+
+			Bookings::<T>::remove(booking_uid);
+			if let Some(payment) = BookingPayments::<T>::take(booking_uid) {
+				T::Currency::unreserve(&payment.buyer, payment.amount);
+			}
+			Self::deposit_event(Event::BookingCancelled(booking_uid));
+
+			Ok(())
+		}
+
+		#[pallet::weight(
+			Pallet::<T>::plan_stops_weight(stops.len() as u64)
+				.saturating_add(Pallet::<T>::create_shipment_weight())
+				.saturating_add(T::DbWeight::get().writes(1))
+		)]
+		#[transactional]
+		pub fn create_multi_drop_shipment(
+			origin: OriginFor<T>,
+			stops: BoundedVec<(T::AccountId, u32), T::MaxSize>,
+			handling: HandlingClass,
+			handoff_mode: HandoffMode,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+			ensure!(
+				!stops.is_empty() &&
+					stops.iter().enumerate().all(|(i, (node, _))| {
+						*node != transit_node && stops[..i].iter().all(|(other, _)| other != node)
+					}),
+				Error::<T>::InvalidRoute);
+
+			let (route, stops) = Self::plan_stops(transit_node.clone(), stops.into_inner(), handling)?;
+			let shipment_uid = Self::do_create_shipment(
+				transit_node.clone(),
+				transit_node.clone(),
+				transit_node,
+				route,
+				handling,
+				None,
+				handoff_mode,
+				key_commitment,
+				sealed_key)?;
+			ShipmentStops::<T>::insert(shipment_uid, stops);
+
+			Ok(())
+		}
+
+		#[pallet::weight(Pallet::<T>::handoff_weight().saturating_add(T::DbWeight::get().reads(2)))]
+		#[transactional]
+		pub fn update_shipment(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			proof: HandoffProofOf<T>,
+			next_key_commitment: Option<T::Hash>,
+			next_sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+			let shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+
+			ensure!(shipment.owner_index > 0, Error::<T>::UnauthorizedCaller);
+			Self::verify_handoff(&shipment, proof)?;
+			ensure!(&transit_node == shipment.route.get(shipment.owner_index as usize).unwrap(), Error::<T>::UnauthorizedCaller);
+
+			Self::do_handoff(shipment, transit_node, <pallet_timestamp::Pallet<T>>::get(), next_key_commitment, next_sealed_key)
+		}
+
+		#[pallet::weight(
+			Pallet::<T>::handoff_weight()
+			.saturating_add(T::DbWeight::get().reads_writes(3, 1))
+			.saturating_mul(vouchers.len() as u64)
+		)]
+		#[transactional]
+		pub fn submit_handoffs(
+			origin: OriginFor<T>,
+			vouchers: BoundedVec<HandoffVoucherOf<T>, T::MaxHandoffBatch>
+		) -> DispatchResult {
+
+			ensure_signed(origin)?;
+			let now = <pallet_timestamp::Pallet<T>>::get();
+			let count = vouchers.len() as u32;
+
+			// Vouchers are applied in order, so several hops of one shipment can be batched
+			// as long as they are listed in route order
+			for voucher in vouchers {
+				let shipment = Self::uid_to_shipment(voucher.shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+				ensure!(shipment.handoff_mode == HandoffMode::MutualSignature, Error::<T>::InvalidHandoffProof);
+				ensure!(voucher.hop > 0 && voucher.hop == shipment.owner_index, Error::<T>::VoucherOutOfOrder);
+
+				let last_moment = Self::custody_log(voucher.shipment_uid).last().map(|entry| entry.moment);
+				ensure!(
+					voucher.moment <= now && last_moment.map_or(true, |last| last <= voucher.moment),
+					Error::<T>::VoucherOutOfOrder);
+
+				let payload = Self::voucher_payload(voucher.shipment_uid, voucher.hop, voucher.moment);
+				let payload_hash = T::Hashing::hash(&payload);
+				ensure!(
+					!ConsumedVouchers::<T>::contains_key(voucher.shipment_uid, payload_hash),
+					Error::<T>::VoucherAlreadyUsed);
+
+				let hop = voucher.hop as usize;
+				let receiver = shipment.route[hop].clone();
+				ensure!(
+					voucher.releaser.verify(&payload[..], &shipment.route[hop - 1]) &&
+						voucher.receiver.verify(&payload[..], &receiver),
+					Error::<T>::InvalidSignature);
+
+				ConsumedVouchers::<T>::insert(voucher.shipment_uid, payload_hash, <frame_system::Pallet<T>>::block_number());
+				Self::do_handoff(shipment, receiver, voucher.moment, None, None)?;
+			}
+
+			Self::deposit_event(Event::HandoffVouchersApplied(count));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, 6))]
+		#[transactional]
+		pub fn reissue_key(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			key_commitment: T::Hash,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+			match ensure_signed(origin.clone()) {
+				Ok(caller) => ensure!(caller == shipment.creator, Error::<T>::UnauthorizedCaller),
+				Err(_) => ensure_root(origin)?,
+			}
+			ensure!(
+				shipment.handoff_mode == HandoffMode::SharedKey && shipment.owner_index > 0,
+				Error::<T>::NoHandoffKey);
+
+			// The lost key is replaced, so it can no longer be used even if it turns up again
+			Self::revoke_handoff_key(shipment_uid);
+			Self::set_handoff_key(shipment_uid, &shipment.route[shipment.owner_index as usize], key_commitment, sealed_key)?;
+
+			Self::deposit_event(Event::HandoffKeyReissued(shipment_uid));
+
+			Ok(())
+		}
+
+		// The legs not travelled yet are refunded and the return route is priced and paid for
+		// leg by leg, at most `MaxSize` legs each
+		#[pallet::weight({
+			let legs = T::MaxSize::get() as u64;
+			Pallet::<T>::find_route_weight().saturating_add(T::DbWeight::get().reads_writes(
+				legs.saturating_mul(3).saturating_add(12),
+				legs.saturating_mul(3).saturating_add(16)))
+		})]
+		#[transactional]
+		pub fn return_to_sender(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let mut shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+			ensure!(shipment.status.can_transition_to(ShipmentStatus::Returning), Error::<T>::ShipmentNotReturnable);
+
+			// The shipment goes back from whoever took custody of it last
+			let holder = Self::current_holder(shipment_uid).ok_or(Error::<T>::ShipmentNotReturnable)?;
+			match ensure_signed(origin.clone()) {
+				Ok(caller) => ensure!(caller == holder, Error::<T>::UnauthorizedCaller),
+				Err(_) => ensure_root(origin)?,
+			}
+			ensure!(holder != shipment.creator, Error::<T>::ShipmentNotReturnable);
+
+			let route = Self::find_route(holder.clone(), shipment.creator.clone(), shipment.handling)?;
+			Self::ensure_custody_room(shipment_uid, &route)?;
+			Self::refund_legs(&mut shipment);
+			let fee = Self::route_fee(&route)?;
+			Self::charge_legs(shipment_uid, &Self::return_payer(&shipment, fee), &route, 0)?;
+
+			// Shipments waiting at their destination no longer count against its capacity
+			if shipment.owner_index > 0 {
+				Self::note_release(&holder);
+			}
+			Self::note_dispatch(&holder, &route[1]);
+			Self::revoke_handoff_key(shipment_uid);
+			if shipment.handoff_mode == HandoffMode::SharedKey {
+				let key_commitment = key_commitment.ok_or(Error::<T>::KeyCommitmentRequired)?;
+				Self::set_handoff_key(shipment_uid, &route[1], key_commitment, sealed_key)?;
+			}
+
+			shipment.fees = Some(shipment.fees.unwrap_or_default().saturating_add(fee));
+			shipment.route = route;
+			shipment.owner_index = 1;
+			Self::transition(&mut shipment, ShipmentStatus::Returning)?;
+			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+
+			Self::deposit_event(Event::ShipmentReturning(shipment_uid));
+
+			Ok(())
+		}
+
+		// The legs not travelled yet are refunded and the new remaining route is priced and paid
+		// for leg by leg, at most `MaxSize` legs each
+		#[pallet::weight({
+			let legs = T::MaxSize::get() as u64;
+			Pallet::<T>::find_route_weight().saturating_add(T::DbWeight::get().reads_writes(
+				legs.saturating_mul(3).saturating_add(9),
+				legs.saturating_mul(3).saturating_add(15)))
+		})]
+		#[transactional]
+		pub fn amend_destination(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			destination: T::AccountId,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			let mut shipment = Self::synced_shipment(shipment_uid)?;
+
+			ensure!(
+				caller == shipment.creator || caller == shipment.beneficial_owner ||
+					shipment.consignee.as_ref() == Some(&caller),
+				Error::<T>::UnauthorizedCaller);
+			ensure!(
+				shipment.status.is_moving() && !Self::is_return_leg(&shipment) &&
+					!ShipmentStops::<T>::contains_key(shipment_uid),
+				Error::<T>::ShipmentNotRedirectable);
+
+			// The shipment continues from whoever took custody of it last. The part of the route
+			// it has already travelled is kept, so hops still match the custody log.
+			let hop = shipment.owner_index as usize;
+			let holder = shipment.route[hop - 1].clone();
+			ensure!(destination != holder, Error::<T>::InvalidRoute);
+			let remaining = Self::find_route(holder.clone(), destination.clone(), shipment.handling)?;
+			Self::ensure_custody_room(shipment_uid, &remaining)?;
+
+			// Whoever paid for the legs not travelled yet gets back what is held for them, and
+			// the caller pays for the new ones
+			Self::refund_legs(&mut shipment);
+			let fee = Self::charge_legs(shipment_uid, &caller, &remaining, (hop - 1) as u8)?;
+			shipment.fees = Some(shipment.fees.unwrap_or_default().saturating_add(fee));
+
+			// Re-target the leg the shipment is on if its next hop changed
+			let old_next = shipment.route[hop].clone();
+			let new_next = remaining[1].clone();
+			if new_next != old_next {
+				Self::mutate_edge_throughput(&holder, &old_next, |count| count.saturating_sub(1));
+				Self::mutate_edge_throughput(&holder, &new_next, |count| count.saturating_add(1));
+				if shipment.handoff_mode == HandoffMode::SharedKey {
+					let key_commitment = key_commitment.ok_or(Error::<T>::KeyCommitmentRequired)?;
+					Self::revoke_handoff_key(shipment_uid);
+					Self::set_handoff_key(shipment_uid, &new_next, key_commitment, sealed_key)?;
+				}
+			}
+
+			let mut route = shipment.route[..hop - 1].to_vec();
+			route.extend(remaining);
+			shipment.route = route.try_into().map_err(|_| Error::<T>::InvalidRoute)?;
+			shipment.destination = destination.clone();
+			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+
+			Self::deposit_event(Event::ShipmentRedirected(shipment_uid, destination));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(5, 9))]
+		pub fn transfer_title(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			new_owner: T::AccountId,
+			require_acceptance: bool
+		) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			let shipment = Self::synced_shipment(shipment_uid)?;
+			ensure!(caller == shipment.beneficial_owner, Error::<T>::UnauthorizedCaller);
+			ensure!(!shipment.status.is_closed(), Error::<T>::ShipmentAlreadyClosed);
+
+			// A new transfer replaces any transfer still waiting to be accepted
+			PendingTitleTransfers::<T>::remove(shipment_uid);
+			if require_acceptance {
+				PendingTitleTransfers::<T>::insert(shipment_uid, &new_owner);
+				Self::deposit_event(Event::TitleTransferProposed(shipment_uid, new_owner));
+				return Ok(())
+			}
+			Self::do_transfer_title(shipment, new_owner)
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(6, 9))]
+		pub fn accept_title(origin: OriginFor<T>, shipment_uid: u64) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			// A transfer proposed by an owner who has since sold the item is dropped
+			let shipment = Self::synced_shipment(shipment_uid)?;
+			let new_owner = Self::pending_title_transfer(shipment_uid).ok_or(Error::<T>::NoPendingTitleTransfer)?;
+			ensure!(caller == new_owner, Error::<T>::UnauthorizedCaller);
+			ensure!(!shipment.status.is_closed(), Error::<T>::ShipmentAlreadyClosed);
+
+			PendingTitleTransfers::<T>::remove(shipment_uid);
+			Self::do_transfer_title(shipment, new_owner)
+		}
+
+		// Closing a shipment refunds the legs it has not travelled, at most `MaxSize`
+		#[pallet::weight({
+			let legs = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads_writes(legs.saturating_add(11), legs.saturating_add(18))
+		})]
+		pub fn set_shipment_status(
+			origin: OriginFor<T>,
+			shipment_uid: u64,
+			status: ShipmentStatus
+		) -> DispatchResult {
+
+			let mut shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+
+			// Every other status follows from handoffs and confirmations
+			let authority = match status {
+				ShipmentStatus::AwaitingPickup |
+				ShipmentStatus::InTransit |
+				ShipmentStatus::OutForDelivery |
+				ShipmentStatus::OnHold => Self::current_holder(shipment_uid),
+				// Losses are paid out of the insurance pool, so only governance declares them
+				ShipmentStatus::Lost => None,
+				ShipmentStatus::Cancelled => {
+					// Shipments can only be cancelled before they leave the creator
+					ensure!(
+						Self::current_holder(shipment_uid).as_ref() == Some(&shipment.creator),
+						Error::<T>::InvalidStatusTransition);
+					Some(shipment.creator.clone())
+				},
+				_ => return Err(Error::<T>::InvalidStatusTransition.into()),
+			};
+			match ensure_signed(origin.clone()) {
+				Ok(caller) => ensure!(Some(caller) == authority, Error::<T>::UnauthorizedCaller),
+				Err(_) => ensure_root(origin)?,
+			}
+
+			Self::transition(&mut shipment, status)?;
+			if status.is_closed() {
+				// Nobody holds the shipment any more
+				if shipment.owner_index > 0 {
+					Self::note_release(&shipment.route[shipment.owner_index as usize - 1]);
+				}
+				shipment.owner_index = 0;
+				Self::revoke_handoff_key(shipment_uid);
+			}
+			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(2, 2))]
+		pub fn bond_node(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+
+			T::Currency::reserve(&transit_node, amount)?;
+			NodeBonds::<T>::mutate(&transit_node, |bond| *bond = bond.saturating_add(amount));
+
+			Self::deposit_event(Event::NodeBonded(transit_node, amount));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, 2))]
+		pub fn unbond_node(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+
+			let transit_node = ensure_signed(origin)?;
+			ensure!(Self::node_bond(&transit_node) >= amount, Error::<T>::InsufficientBond);
+			// The bond stays liable for every shipment the node still holds
+			ensure!(Self::node_holdings(&transit_node) == 0, Error::<T>::NodeHoldingShipments);
+
+			T::Currency::unreserve(&transit_node, amount);
+			NodeBonds::<T>::mutate(&transit_node, |bond| *bond = bond.saturating_sub(amount));
+
+			Self::deposit_event(Event::NodeUnbonded(transit_node, amount));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(5, 6))]
+		pub fn insure_shipment(origin: OriginFor<T>, shipment_uid: u64, declared_value: BalanceOf<T>) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			let shipment = Self::synced_shipment(shipment_uid)?;
+			ensure!(caller == shipment.beneficial_owner, Error::<T>::UnauthorizedCaller);
+			// Cover can only be bought before the shipment leaves its first node
+			ensure!(shipment.status == ShipmentStatus::Created, Error::<T>::ShipmentNotInsurable);
+			ensure!(!Policies::<T>::contains_key(shipment_uid), Error::<T>::AlreadyInsured);
+
+			// Whoever pays into an empty pool tops it up to the existential deposit, so premiums
+			// of any size can be paid in after it. Claims never pay out that part.
+			let pool = Self::insurance_pool();
+			let premium = T::InsurancePremium::get() * declared_value;
+			let payment = match T::Currency::total_balance(&pool).is_zero() {
+				true => premium.max(T::Currency::minimum_balance()),
+				false => premium,
+			};
+			T::Currency::transfer(&caller, &pool, payment, ExistenceRequirement::KeepAlive)?;
+			Policies::<T>::insert(shipment_uid, InsurancePolicy { declared_value, premium, recovered: Zero::zero() });
+
+			Self::deposit_event(Event::ShipmentInsured(shipment_uid, declared_value));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(5, 3))]
+		pub fn file_claim(origin: OriginFor<T>, shipment_uid: u64) -> DispatchResult {
+
+			let caller = ensure_signed(origin)?;
+			// The title was settled and the item frozen when the shipment was lost
+			let shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+			ensure!(caller == shipment.beneficial_owner, Error::<T>::UnauthorizedCaller);
+			ensure!(shipment.status == ShipmentStatus::Lost, Error::<T>::ShipmentNotLost);
+			let policy = Self::policy(shipment_uid).ok_or(Error::<T>::NotInsured)?;
+
+			// Nothing is paid for goods that never left their first node, or that were lost by
+			// their own owner or creator
+			let log = Self::custody_log(shipment_uid);
+			ensure!(log.len() > 1, Error::<T>::ShipmentNotClaimable);
+			let holder = &log[log.len() - 1].handler;
+			ensure!(*holder != shipment.beneficial_owner && *holder != shipment.creator, Error::<T>::ShipmentNotClaimable);
+
+			// A payout is what was recovered from the node plus a bounded share of the rest of
+			// the pool, and never more than the pool holds
+			let pool = Self::insurance_pool();
+			let available = T::Currency::free_balance(&pool).saturating_sub(T::Currency::minimum_balance());
+			let recovered = policy.recovered.min(available);
+			let cap = recovered.saturating_add(T::MaxClaimShare::get() * available.saturating_sub(recovered));
+			let payout = policy.declared_value.min(cap);
+			T::Currency::transfer(&pool, &caller, payout, ExistenceRequirement::KeepAlive)?;
+			Policies::<T>::remove(shipment_uid);
+
+			Self::deposit_event(Event::ClaimPaid(shipment_uid, caller, payout));
+
+			Ok(())
+		}
+
+		#[pallet::weight(T::DbWeight::get().reads_writes(9, 13))]
+		pub fn confirm_receipt(origin: OriginFor<T>, shipment_uid: u64) -> DispatchResult {
 
-			Self::deposit_event(Event::NeighbourUpdated(node1,node2));
+			let consignee = ensure_signed(origin)?;
+			let mut shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+
+			ensure!(shipment.consignee.as_ref() == Some(&consignee), Error::<T>::UnauthorizedCaller);
+			ensure!(shipment.status == ShipmentStatus::AwaitingConfirmation, Error::<T>::ShipmentNotAwaitingConfirmation);
+
+			Self::transition(&mut shipment, ShipmentStatus::Delivered)?;
+			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+
+			Self::deposit_event(Event::ShipmentConfirmed(shipment_uid));
 
 			Ok(())
 		}
 
-		#[pallet::weight(0)]
-		pub fn remove_transit_node(origin: OriginFor<T>, transit_node: T::AccountId) -> DispatchResult {
-
-			ensure_root(origin)?;
-			ensure!(Self::transit_nodes().contains(&transit_node), Error::<T>::TransitPointNotFound);
+		// A shipment consumed at most one voucher per hop of a trip out and back
+		#[pallet::weight({
+			let vouchers = MaxCustodyEntries::<T>::get() as u64;
+			T::DbWeight::get().reads_writes(6, vouchers.saturating_add(18))
+		})]
+		pub fn reap_shipment(origin: OriginFor<T>, shipment_uid: u64) -> DispatchResult {
 
-			RouteCosts::<T>::remove_prefix(&transit_node, None);
-			for node in Self::transit_nodes() {
-				if node == transit_node {
-					continue;
-				}
-				if RouteCosts::<T>::contains_key(&node, &transit_node) {
-					RouteCosts::<T>::remove(&node, &transit_node);
+			ensure_signed(origin)?;
+			let shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+			let closed_at = Self::shipment_closed_at(shipment_uid).ok_or(Error::<T>::ShipmentNotClosed)?;
+			ensure!(
+				<frame_system::Pallet<T>>::block_number() >= closed_at + T::RetentionPeriod::get(),
+				Error::<T>::RetentionPeriodNotElapsed);
+			T::ShipmentItems::burn(shipment_uid)?;
+
+			let summary = ShipmentSummary::<T> {
+				uid: shipment_uid,
+				creator: shipment.creator,
+				destination: shipment.destination,
+				status: shipment.status,
+				closed_at,
+			};
+			sp_io::offchain_index::set(&(ARCHIVE_INDEX_PREFIX, shipment_uid).encode(), &summary.encode());
+			ArchivedShipments::<T>::mutate(|archive| {
+				if !archive.is_empty() && archive.len() as u32 >= T::MaxArchivedShipments::get() {
+					archive.remove(0);
 				}
+				let _ = archive.try_push(summary);
+			});
+
+			UIDToShipment::<T>::remove(shipment_uid);
+			Self::revoke_handoff_key(shipment_uid);
+			CustodyLog::<T>::remove(shipment_uid);
+			TitleHistory::<T>::remove(shipment_uid);
+			PendingTitleTransfers::<T>::remove(shipment_uid);
+			ShipmentStops::<T>::remove(shipment_uid);
+			let _ = ConsumedVouchers::<T>::remove_prefix(shipment_uid, None);
+			ShipmentClosedAt::<T>::remove(shipment_uid);
+			Policies::<T>::remove(shipment_uid);
+			ShipmentSponsors::<T>::remove(shipment_uid);
+			if let Some((depositor, deposit)) = ShipmentDeposits::<T>::take(shipment_uid) {
+				T::Currency::unreserve(&depositor, deposit);
 			}
 
-			let transit_point_counts = Self::count_for_transit_point().checked_sub(1).ok_or(ArithmeticError::Underflow)?;
-			let mut new_transit_nodes = Self::transit_nodes();
-			new_transit_nodes.retain(|nodes| *nodes == transit_node);
+			Self::deposit_event(Event::ShipmentReaped(shipment_uid));
 
-			CountForTransitPoints::<T>::put(transit_point_counts);
-			TransitNodes::<T>::put(new_transit_nodes);
+			Ok(())
+		}
 
-			Self::deposit_event(Event::TransitPointRemoved(transit_node));
+		// Lets anyone record a trade of a shipment's item made outside the pallet right away,
+		// rather than when the shipment is next acted on
+		#[pallet::weight(T::DbWeight::get().reads_writes(3, 4))]
+		pub fn sync_title(origin: OriginFor<T>, shipment_uid: u64) -> DispatchResult {
+
+			ensure_signed(origin)?;
+			Self::synced_shipment(shipment_uid)?;
 
 			Ok(())
 		}
+	}
+
+	// Helpful functions
+	impl<T: Config> Pallet<T> {
+
+		// Upper bound on finding a route between two nodes. A precomputed route is checked node
+		// by node and leg by leg for capabilities and capacity. A random route also reads every
+		// transit node as a candidate stop and draws up to three random numbers.
+		fn find_route_weight() -> Weight {
+			let nodes = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads_writes(nodes.saturating_mul(8).saturating_add(8), 3)
+		}
+
+		// Upper bound on `plan_stops`: the precomputed route to every remaining stop is read and
+		// priced from every stop planned so far. Only the chosen routes, at most `MaxSize` nodes
+		// in all, are checked for capabilities and capacity.
+		fn plan_stops_weight(stops: u64) -> Weight {
+			let nodes = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads(
+				stops.saturating_mul(stops)
+					.saturating_mul(nodes.saturating_add(1))
+					.saturating_add(nodes.saturating_mul(6)))
+		}
 
-		#[pallet::weight(0)]
-		pub fn create_shipment(origin: OriginFor<T>, destination: T::AccountId) -> DispatchResult {
+		// Upper bound on `do_create_shipment`: every leg of the route is priced and its fee
+		// recorded, then the fee, the deposit, the item, the custody and title logs, the key and
+		// the shipment are written
+		fn create_shipment_weight() -> Weight {
+			let legs = T::MaxSize::get() as u64;
+			T::DbWeight::get().reads_writes(legs.saturating_add(18), legs.saturating_add(18))
+		}
 
-			let transit_node = ensure_signed(origin)?;
+		// Upper bound on `do_handoff`, including settling the leg, closing the shipment and
+		// settling its payment
+		fn handoff_weight() -> Weight {
+			T::DbWeight::get().reads_writes(19, 25)
+		}
 
+		// Great-circle distance in whole kilometres times the mode multiplier. `None` when
+		// either endpoint has no location yet.
+		fn derive_edge_cost(node1: &T::AccountId, node2: &T::AccountId, mode: TransportMode) -> Option<u32> {
+			let from = Self::node_location(node1)?;
+			let to = Self::node_location(node2)?;
+			let kilometres = (geo::great_circle_distance(&from, &to) / 1000).max(1);
+			let cost = kilometres.saturating_mul(mode.cost_multiplier()) / 100;
+			Some(cost.max(1))
+		}
+
+		// Total cost of `route`. Fails if any leg of it is not an edge.
+		fn route_cost(route: &[T::AccountId]) -> Result<u32, Error<T>> {
+			route.windows(2).try_fold(0u32, |total, leg| {
+				let cost = Self::route_costs(&leg[0], &leg[1]).ok_or(Error::<T>::InvalidRoute)?;
+				Ok(total.saturating_add(cost))
+			})
+		}
+
+		// Fee for carrying a shipment along `route`: its total cost times `FeePerCostUnit`.
+		// Routes are only priced when fees are enabled, and fail if a leg has no cost.
+		fn route_fee(route: &[T::AccountId]) -> Result<BalanceOf<T>, Error<T>> {
+			let per_unit = T::FeePerCostUnit::get();
+			if per_unit.is_zero() {
+				return Ok(Zero::zero())
+			}
+			let cost = Self::route_cost(route)?;
+			Ok(per_unit.saturating_mul(cost.into()))
+		}
+
+		// Charges `payer` for `route`, reserves the deposit from it and starts the shipment at
+		// the first node of the route, with the goods belonging to `owner`
+		#[allow(clippy::too_many_arguments)]
+		fn do_create_shipment(
+			creator: T::AccountId,
+			owner: T::AccountId,
+			payer: T::AccountId,
+			route: BoundedVec<T::AccountId, T::MaxSize>,
+			handling: HandlingClass,
+			consignee: Option<T::AccountId>,
+			handoff_mode: HandoffMode,
+			key_commitment: Option<T::Hash>,
+			sealed_key: Option<SealedKey>
+		) -> Result<u64, DispatchError> {
 			let shipment_uid = Self::shipment_uid().checked_add(1).ok_or(ArithmeticError::Overflow)?;
-			
-			//let route1 = Self::route_vec(transit_node.clone(),destination.clone()).unwrap();
+
+			let fee = Self::charge_legs(shipment_uid, &payer, &route, 0)?;
 
 			let shipment = Shipment::<T> {
-				creator: transit_node.clone(),
-				fees: None, // Todo: Calculate fees based on the route
+				creator: creator.clone(),
+				beneficial_owner: owner.clone(),
+				fees: Some(fee),
 				owner_index: 1,
-				route: Self::get_random_route(transit_node.clone(),destination.clone()),
-				destination: destination.clone(),
-				uid: shipment_uid.clone(),
-				status: ShipmentStatus::InTransit
+				destination: route[route.len() - 1].clone(),
+				route,
+				uid: shipment_uid,
+				status: ShipmentStatus::Created,
+				handling,
+				consignee,
+				handoff_mode,
 			};
 
 			ensure!(!UIDToShipment::<T>::contains_key(&shipment_uid), Error::<T>::ShipmentAlreadyExists);
+			Self::note_dispatch(&shipment.route[0], &shipment.route[1]);
+			Self::log_custody(shipment_uid, creator.clone(), 0, <pallet_timestamp::Pallet<T>>::get())?;
+			Self::log_title(shipment_uid, owner.clone())?;
+			T::ShipmentItems::mint(shipment_uid, &owner)?;
+			T::ShipmentItems::set_status(shipment_uid, ShipmentStatus::Created)?;
+
+			let deposit = T::ShipmentDeposit::get();
+			T::Currency::reserve(&payer, deposit)?;
+			ShipmentDeposits::<T>::insert(shipment_uid, (payer, deposit));
+
 			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
 
-			let key = Self::gen_key();
-			UIDToKey::<T>::insert(&shipment_uid, &key);
+			if handoff_mode == HandoffMode::SharedKey {
+				let key_commitment = key_commitment.ok_or(Error::<T>::KeyCommitmentRequired)?;
+				Self::set_handoff_key(shipment_uid, &shipment.route[1], key_commitment, sealed_key)?;
+			}
 			ShipmentUID::<T>::put(shipment_uid);
 
-			Self::deposit_event(Event::ShipmentCreated(transit_node));
+			Self::deposit_event(Event::ShipmentCreated(creator));
+
+			Ok(shipment_uid)
+		}
+
+		// Orders the stops greedily, always heading for the one cheapest to reach next by its
+		// precomputed route, and chains the routes between them. Every stop needs a current
+		// precomputed route, stops are never reached by a random route.
+		fn plan_stops(
+			origin: T::AccountId,
+			mut stops: Vec<(T::AccountId, u32)>,
+			handling: HandlingClass
+		) -> Result<(BoundedVec<T::AccountId, T::MaxSize>, BoundedVec<StopOf<T>, T::MaxSize>), DispatchError> {
+
+			ensure!(Self::route_vector_version() == Self::topology_version(), Error::<T>::StaleRouteVectors);
+
+			let mut route = vec![origin.clone()];
+			let mut planned = Vec::new();
+			let mut at = origin;
+			while !stops.is_empty() {
+				let mut cheapest: Option<(usize, Vec<T::AccountId>, u32)> = None;
+				for (i, (node, _)) in stops.iter().enumerate() {
+					let leg = Self::route_vec(&at, node).ok_or(Error::<T>::NoPrecomputedRoute)?;
+					let cost = Self::route_cost(&leg)?;
+					if cheapest.as_ref().map_or(true, |(_, _, lowest)| cost < *lowest) {
+						cheapest = Some((i, leg, cost));
+					}
+				}
+				let (i, leg, _) = cheapest.ok_or(Error::<T>::InvalidRoute)?;
+				ensure!(leg.iter().all(|node| Self::can_handle(node, handling)), Error::<T>::NoCompliantRoute);
+				ensure!(Self::route_has_capacity(&leg), Error::<T>::RouteCapacityExhausted);
+				let (node, items) = stops.remove(i);
+
+				route.extend(leg.into_iter().skip(1));
+				ensure!(route.len() <= T::MaxSize::get() as usize, Error::<T>::InvalidRoute);
+				planned.push(Stop { node: node.clone(), items, hop: (route.len() - 1) as u8, delivered_at: None });
+				at = node;
+			}
+
+			let route = route.try_into().map_err(|_| Error::<T>::InvalidRoute)?;
+			let planned = planned.try_into().map_err(|_| Error::<T>::InvalidRoute)?;
+			Ok((route, planned))
+		}
+
+		// Records a partial delivery if hop `hop` of a multi-drop shipment is one of its stops
+		fn record_drop_off(shipment_uid: u64, hop: u8) {
+			ShipmentStops::<T>::mutate(shipment_uid, |stops| {
+				if let Some(stop) = stops.as_mut().and_then(|stops| stops.iter_mut().find(|stop| stop.hop == hop)) {
+					stop.delivered_at = Some(<frame_system::Pallet<T>>::block_number());
+					Self::deposit_event(Event::StopDelivered(shipment_uid, stop.node.clone()));
+				}
+			});
+		}
+
+		// Charges `payer` for every leg of `route`, which starts at hop `first_hop` of the
+		// shipment, and returns the total fee. `TreasuryShare` of each leg's fee goes to
+		// `FeeDestination` and the rest is held from the payer until the leg is travelled, when it
+		// goes to the node that carried the shipment along it.
+		fn charge_legs(
+			shipment_uid: u64,
+			payer: &T::AccountId,
+			route: &[T::AccountId],
+			first_hop: u8
+		) -> Result<BalanceOf<T>, DispatchError> {
+			let mut fee: BalanceOf<T> = Zero::zero();
+			let mut held: BalanceOf<T> = Zero::zero();
+			for (i, leg) in route.windows(2).enumerate() {
+				let leg_fee = Self::route_fee(leg)?;
+				if leg_fee.is_zero() {
+					continue
+				}
+				let leg_held = leg_fee.saturating_sub(T::TreasuryShare::get() * leg_fee);
+				let hop = u8::try_from(i + 1).ok().and_then(|i| first_hop.checked_add(i)).ok_or(Error::<T>::InvalidRoute)?;
+				LegFees::<T>::insert(shipment_uid, hop, (payer.clone(), leg_held));
+				fee = fee.saturating_add(leg_fee);
+				held = held.saturating_add(leg_held);
+			}
+
+			if !fee.is_zero() {
+				let share = fee - held;
+				let treasury = T::Currency::withdraw(payer, share, WithdrawReasons::FEE, ExistenceRequirement::KeepAlive)?;
+				T::FeeDestination::on_unbalanced(treasury);
+				T::Currency::reserve(payer, held)?;
+				Self::deposit_event(Event::FeeCharged(payer.clone(), fee, share));
+			}
+			Ok(fee)
+		}
+
+		// The leg into hop `hop` has been travelled, so the fee held for it is paid to the node
+		// that sent the shipment along it. Handoffs never fail over the payment, if it can't be
+		// made the fee goes back to the payer.
+		fn settle_leg(shipment: &Shipment<T>, hop: u8) {
+			if let Some((payer, held)) = LegFees::<T>::take(shipment.uid, hop) {
+				let carrier = &shipment.route[hop as usize - 1];
+				match T::Currency::repatriate_reserved(&payer, carrier, held, BalanceStatus::Free) {
+					Ok(unpaid) => {
+						Self::deposit_event(Event::LegFeePaid(shipment.uid, carrier.clone(), held.saturating_sub(unpaid)));
+					},
+					Err(_) => {
+						T::Currency::unreserve(&payer, held);
+					},
+				}
+			}
+		}
+
+		// Gives back what is held for the legs not travelled yet to whoever paid for them. The
+		// treasury keeps its share. A sponsor's refund is credited back to the allowance of the
+		// shipment's creator.
+		fn refund_legs(shipment: &mut Shipment<T>) {
+			let mut refunds: Vec<(T::AccountId, BalanceOf<T>)> = Vec::new();
+			for (_, (payer, held)) in LegFees::<T>::drain_prefix(shipment.uid) {
+				match refunds.iter_mut().find(|(account, _)| *account == payer) {
+					Some((_, amount)) => *amount = amount.saturating_add(held),
+					None => refunds.push((payer, held)),
+				}
+			}
+
+			let sponsor = Self::shipment_sponsor(shipment.uid);
+			for (payer, held) in refunds {
+				let refund = held.saturating_sub(T::Currency::unreserve(&payer, held));
+				if sponsor.as_ref() == Some(&payer) {
+					Sponsorships::<T>::mutate(&payer, &shipment.creator, |sponsorship| {
+						if let Some(sponsorship) = sponsorship {
+							sponsorship.spent = sponsorship.spent.saturating_sub(refund);
+						}
+					});
+				}
+				shipment.fees = Some(shipment.fees.unwrap_or_default().saturating_sub(refund));
+				Self::deposit_event(Event::FeeRefunded(payer, refund));
+			}
+		}
+
+		// Uses `fee` of what `sponsor` allows `creator` to spend in the current period
+		fn spend_allowance(sponsor: &T::AccountId, creator: &T::AccountId, fee: BalanceOf<T>) -> DispatchResult {
+			Sponsorships::<T>::try_mutate(sponsor, creator, |sponsorship| {
+				let sponsorship = sponsorship.as_mut().ok_or(Error::<T>::NoSponsorship)?;
+				let now = <frame_system::Pallet<T>>::block_number();
+				if now >= sponsorship.period_start + T::SponsorshipPeriod::get() {
+					sponsorship.spent = Zero::zero();
+					sponsorship.period_start = now;
+				}
+				let spent = sponsorship.spent.saturating_add(fee);
+				ensure!(spent <= sponsorship.limit, Error::<T>::SponsorshipExceeded);
+				sponsorship.spent = spent;
+				Ok(())
+			})
+		}
+
+		// Account that pays for the shipment: the customer who booked it, its sponsor, or else
+		// its creator
+		fn payer(shipment: &Shipment<T>) -> T::AccountId {
+			Self::shipment_deposit(shipment.uid)
+				.map(|(payer, _)| payer)
+				.unwrap_or_else(|| shipment.creator.clone())
+		}
+
+		// Whoever paid for a shipment pays `fee` for its return. A sponsor only pays within its
+		// allowance, beyond it or once the sponsorship is revoked the creator is billed.
+		fn return_payer(shipment: &Shipment<T>, fee: BalanceOf<T>) -> T::AccountId {
+			match Self::shipment_sponsor(shipment.uid) {
+				Some(sponsor) => match Self::spend_allowance(&sponsor, &shipment.creator, fee) {
+					Ok(()) => sponsor,
+					Err(_) => shipment.creator.clone(),
+				},
+				None => Self::payer(shipment),
+			}
+		}
+
+		// Return routes start at the node that sent the shipment back
+		fn is_return_leg(shipment: &Shipment<T>) -> bool {
+			shipment.route[0] != shipment.creator
+		}
+
+		// Expects `key_commitment` from `next_holder`, expiring after `KeyLifetime` blocks
+		fn set_handoff_key(
+			shipment_uid: u64,
+			next_holder: &T::AccountId,
+			key_commitment: T::Hash,
+			sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+			Self::store_sealed_key(shipment_uid, next_holder, sealed_key)?;
+			UIDToKey::<T>::insert(shipment_uid, key_commitment);
+
+			let lifetime = T::KeyLifetime::get();
+			if !lifetime.is_zero() {
+				let expires_at = <frame_system::Pallet<T>>::block_number() + lifetime;
+				KeyExpiresAt::<T>::insert(shipment_uid, expires_at);
+				KeyExpiries::<T>::append(expires_at, shipment_uid);
+			}
+			Ok(())
+		}
+
+		// Replaces the key of `shipment_uid` with a fresh one sealed to the shipment's next
+		// holder. Only possible if the next holder registered a handoff key. Returns whether the
+		// key was replaced.
+		fn rotate_handoff_key(shipment_uid: u64) -> bool {
+			let next_holder = match Self::uid_to_shipment(shipment_uid) {
+				Some(shipment) if shipment.handoff_mode == HandoffMode::SharedKey && shipment.owner_index > 0 =>
+					shipment.route.get(shipment.owner_index as usize).cloned(),
+				_ => None,
+			};
+			let (next_holder, recipient) = match next_holder {
+				Some(node) => match Self::handoff_key(&node) {
+					Some(recipient) => (node, recipient),
+					None => return false,
+				},
+				None => return false,
+			};
+
+			let key = Self::gen_key();
+			let recipient = sp_core::ed25519::Public::from(recipient).0;
+			let sealed_key = match sealing::seal(&recipient, &Self::gen_seed(), &key) {
+				Some(sealed_key) => sealed_key,
+				None => return false,
+			};
+			Self::revoke_handoff_key(shipment_uid);
+			Self::set_handoff_key(shipment_uid, &next_holder, T::Hashing::hash(&key), Some(sealed_key)).is_ok()
+		}
+
+		fn revoke_handoff_key(shipment_uid: u64) {
+			UIDToKey::<T>::remove(shipment_uid);
+			SealedKeys::<T>::remove(shipment_uid);
+			KeyExpiresAt::<T>::remove(shipment_uid);
+		}
 
+		// Nodes that registered a handoff key only ever receive keys sealed to it
+		fn store_sealed_key(shipment_uid: u64, next_holder: &T::AccountId, sealed_key: Option<SealedKey>) -> DispatchResult {
+			match sealed_key {
+				Some(sealed_key) => SealedKeys::<T>::insert(shipment_uid, sealed_key),
+				None => ensure!(!HandoffKeys::<T>::contains_key(next_holder), Error::<T>::SealedKeyRequired),
+			}
 			Ok(())
 		}
 
-		#[pallet::weight(0)]
-		pub fn update_shipment(origin: OriginFor<T>, shipment_uid: u64, key: [u8; 16]) -> DispatchResult {
+		/// Payload both parties sign offline to produce a `HandoffVoucher`.
+		pub fn voucher_payload(shipment_uid: u64, hop: u8, moment: T::Moment) -> Vec<u8> {
+			(VOUCHER_CONTEXT, shipment_uid, hop, moment).encode()
+		}
 
-			let transit_node = ensure_signed(origin)?;
-			let mut shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+		/// Payload both parties sign to attest the handoff of a shipment to hop `hop` of its
+		/// route, at block `at`.
+		pub fn handoff_payload(shipment_uid: u64, hop: u8, at: T::BlockNumber) -> Vec<u8> {
+			(HANDOFF_CONTEXT, shipment_uid, hop, at).encode()
+		}
 
-			ensure!(UIDToKey::<T>::contains_key(&shipment_uid), Error::<T>::UIDNotFound);
-			ensure!(Self::shipment_uid_to_key(&shipment_uid).unwrap() == key, Error::<T>::InvalidKey);
-			ensure!(UIDToShipment::<T>::contains_key(&shipment_uid), Error::<T>::ShipmentNotFound);
-			ensure!(&transit_node == shipment.route.get(shipment.owner_index as usize).unwrap(), Error::<T>::UnauthorizedCaller);
+		// Checks the proof for handing the shipment to its current receiver
+		fn verify_handoff(shipment: &Shipment<T>, proof: HandoffProofOf<T>) -> DispatchResult {
+			match (shipment.handoff_mode, proof) {
+				(HandoffMode::SharedKey, HandoffProof::Key(key)) => {
+					let commitment = Self::shipment_uid_to_key(shipment.uid).ok_or(Error::<T>::UIDNotFound)?;
+					ensure!(commitment == T::Hashing::hash(&key), Error::<T>::InvalidKey);
+				},
+				(HandoffMode::MutualSignature, HandoffProof::Signatures { at, releaser, receiver }) => {
+					let now = <frame_system::Pallet<T>>::block_number();
+					ensure!(at <= now && now <= at + T::HandoffValidity::get(), Error::<T>::HandoffExpired);
+
+					let hop = shipment.owner_index;
+					let payload = Self::handoff_payload(shipment.uid, hop, at);
+					ensure!(
+						releaser.verify(&payload[..], &shipment.route[hop as usize - 1]) &&
+							receiver.verify(&payload[..], &shipment.route[hop as usize]),
+						Error::<T>::InvalidSignature);
+				},
+				_ => return Err(Error::<T>::InvalidHandoffProof.into()),
+			}
+			Ok(())
+		}
 
-			UIDToKey::<T>::remove(&shipment_uid);
+		// Hands a verified shipment over to its current receiver `transit_node`, who took it
+		// at `moment`
+		fn do_handoff(
+			mut shipment: Shipment<T>,
+			transit_node: T::AccountId,
+			moment: T::Moment,
+			next_key_commitment: Option<T::Hash>,
+			next_sealed_key: Option<SealedKey>
+		) -> DispatchResult {
+			let shipment_uid = shipment.uid;
+			ensure!(shipment.status.is_moving(), Error::<T>::ShipmentNotMoving);
+			let returning = Self::is_return_leg(&shipment);
+
+			Self::revoke_handoff_key(shipment_uid);
+			Self::note_release(&shipment.route[shipment.owner_index as usize - 1]);
+			Self::settle_leg(&shipment, shipment.owner_index);
+			Self::log_custody(shipment_uid, transit_node.clone(), shipment.owner_index, moment)?;
+			if !returning {
+				Self::record_drop_off(shipment_uid, shipment.owner_index);
+			}
 
-			match transit_node == shipment.destination {
+			match shipment.owner_index as usize == shipment.route.len() - 1 {
+				true if returning => {
+					// Shipment is back with its creator
+					shipment.owner_index = 0;
+					Self::transition(&mut shipment, ShipmentStatus::Returned)?;
+					UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+					Self::deposit_event(Event::ShipmentReturned(shipment_uid));
+				},
 				true => {
 					// Shipment has reached end destination
 					shipment.owner_index = 0;
-					shipment.status = ShipmentStatus::Delivered;
+					match shipment.consignee {
+						Some(_) => {
+							// Delivery is only final once the consignee signs for it
+							Self::transition(&mut shipment, ShipmentStatus::AwaitingConfirmation)?;
+							let deadline = <frame_system::Pallet<T>>::block_number() + T::AutoConfirmPeriod::get();
+							ConfirmationDeadlines::<T>::append(deadline, shipment_uid);
+							Self::deposit_event(Event::ShipmentAwaitingConfirmation(shipment_uid));
+						},
+						None => Self::transition(&mut shipment, ShipmentStatus::Delivered)?,
+					}
 					UIDToShipment::<T>::insert(&shipment_uid, &shipment);
 					Self::deposit_event(Event::ShipmentReceived(transit_node));
 				},
 				false => {
-					// Shipment is still in transit
+					// Shipment is still in transit, the caller seals the key for the next hop
+					let status = if returning { ShipmentStatus::Returning } else { ShipmentStatus::AtHub };
+					Self::transition(&mut shipment, status)?;
 					shipment.owner_index = shipment.owner_index + 1;
-					let new_key = Self::gen_key();
-					UIDToKey::<T>::insert(&shipment_uid, &new_key);
+					Self::note_dispatch(&transit_node, &shipment.route[shipment.owner_index as usize]);
+					if shipment.handoff_mode == HandoffMode::SharedKey {
+						let next_key_commitment = next_key_commitment.ok_or(Error::<T>::KeyCommitmentRequired)?;
+						Self::set_handoff_key(shipment_uid, &shipment.route[shipment.owner_index as usize], next_key_commitment, next_sealed_key)?;
+					}
 					UIDToShipment::<T>::insert(&shipment_uid, &shipment);
 					Self::deposit_event(Event::ShipmentUpdated(transit_node));
 				}
@@ -313,18 +2487,137 @@
 
 			Ok(())
 		}
-	}
 
-	// Helpful functions
-	impl<T: Config> Pallet<T> {
+		// Moves `shipment` to `status` if its lifecycle allows it. Closed shipments become
+		// reapable. The caller stores the shipment.
+		fn transition(shipment: &mut Shipment<T>, status: ShipmentStatus) -> DispatchResult {
+			ensure!(shipment.status.can_transition_to(status), Error::<T>::InvalidStatusTransition);
 
-		fn gen_key() -> [u8; 16] {
-			let payload = (
-				T::KeyRandomNess::random(&b"key"[..]).0,
-				<frame_system::Pallet<T>>::extrinsic_index().unwrap_or_default(),
-				<frame_system::Pallet<T>>::block_number(),
-			);
-			payload.using_encoded(blake2_128)
+			let previous = shipment.status;
+			shipment.status = status;
+			if previous != status {
+				T::ShipmentItems::set_status(shipment.uid, status)?;
+				Self::deposit_event(Event::ShipmentStatusChanged(shipment.uid, previous, status));
+			}
+			if status.is_closed() {
+				Self::close_shipment(shipment.uid);
+				Self::refund_legs(shipment);
+				// The goods belong to whoever holds the item when it is frozen
+				Self::mirror_item_owner(shipment);
+				T::ShipmentItems::lock(shipment.uid)?;
+				Self::settle_goods_payment(shipment.uid, status)?;
+			}
+			if status == ShipmentStatus::Lost {
+				Self::subrogate_loss(shipment.uid)?;
+			}
+			Ok(())
+		}
+
+		fn close_shipment(shipment_uid: u64) {
+			ShipmentClosedAt::<T>::insert(shipment_uid, <frame_system::Pallet<T>>::block_number());
+		}
+
+		// Pays the seller once the goods are delivered and refunds the buyer if they never are.
+		// Carrier fees are settled separately.
+		fn settle_goods_payment(shipment_uid: u64, status: ShipmentStatus) -> DispatchResult {
+			if let Some(payment) = GoodsPayments::<T>::take(shipment_uid) {
+				if status == ShipmentStatus::Delivered {
+					T::Currency::repatriate_reserved(&payment.buyer, &payment.seller, payment.amount, BalanceStatus::Free)?;
+					Self::deposit_event(Event::PaymentReleased(shipment_uid, payment.seller, payment.amount));
+				} else {
+					T::Currency::unreserve(&payment.buyer, payment.amount);
+					Self::deposit_event(Event::PaymentRefunded(shipment_uid, payment.buyer, payment.amount));
+				}
+			}
+			Ok(())
+		}
+
+		// The node an insured shipment was lost by pays into the pool from its bond, up to the
+		// shipment's declared value
+		fn subrogate_loss(shipment_uid: u64) -> DispatchResult {
+			let (policy, node) = match (Self::policy(shipment_uid), Self::current_holder(shipment_uid)) {
+				(Some(policy), Some(node)) => (policy, node),
+				_ => return Ok(()),
+			};
+			let amount = Self::node_bond(&node).min(policy.declared_value);
+			if amount.is_zero() {
+				return Ok(())
+			}
+
+			let unmoved = T::Currency::repatriate_reserved(&node, &Self::insurance_pool(), amount, BalanceStatus::Free)?;
+			let slashed = amount.saturating_sub(unmoved);
+			NodeBonds::<T>::mutate(&node, |bond| *bond = bond.saturating_sub(slashed));
+			Policies::<T>::insert(shipment_uid, InsurancePolicy { recovered: slashed, ..policy });
+
+			Self::deposit_event(Event::NodeSlashed(node, slashed));
+			Ok(())
+		}
+
+		// The node that took custody of the shipment last
+		fn current_holder(shipment_uid: u64) -> Option<T::AccountId> {
+			Self::custody_log(shipment_uid).last().map(|entry| entry.handler.clone())
+		}
+
+		// Whether the custody log has room for a handoff to every node of `route` but the first
+		fn ensure_custody_room(shipment_uid: u64, route: &[T::AccountId]) -> DispatchResult {
+			let room = (MaxCustodyEntries::<T>::get() as usize).saturating_sub(Self::custody_log(shipment_uid).len());
+			ensure!(route.len().saturating_sub(1) <= room, Error::<T>::CustodyLogFull);
+			Ok(())
+		}
+
+		fn log_custody(shipment_uid: u64, handler: T::AccountId, hop: u8, moment: T::Moment) -> DispatchResult {
+			let entry = CustodyEntry {
+				handler,
+				block: <frame_system::Pallet<T>>::block_number(),
+				moment,
+				hop,
+			};
+			CustodyLog::<T>::try_mutate(shipment_uid, |log| log.try_push(entry).map_err(|_| Error::<T>::CustodyLogFull))?;
+			Ok(())
+		}
+
+		fn log_title(shipment_uid: u64, owner: T::AccountId) -> DispatchResult {
+			let entry = TitleEntry { owner, block: <frame_system::Pallet<T>>::block_number() };
+			TitleHistory::<T>::try_mutate(shipment_uid, |history| {
+				history.try_push(entry).map_err(|_| Error::<T>::TitleHistoryFull)
+			})?;
+			Ok(())
+		}
+
+		// Items can be traded with any NFT tooling while their shipment is open, so whoever
+		// holds the item owns the goods. Records the item's owner as the beneficial owner if it
+		// moved since, and drops a pending title transfer proposed by the previous owner.
+		fn mirror_item_owner(shipment: &mut Shipment<T>) {
+			let holder = match T::ShipmentItems::owner(shipment.uid) {
+				Some(holder) if holder != shipment.beneficial_owner => holder,
+				_ => return,
+			};
+			// The item is authoritative, even once the history has no room left
+			let _ = Self::log_title(shipment.uid, holder.clone());
+			PendingTitleTransfers::<T>::remove(shipment.uid);
+			shipment.beneficial_owner = holder.clone();
+			UIDToShipment::<T>::insert(shipment.uid, &*shipment);
+			Self::deposit_event(Event::TitleTransferred(shipment.uid, holder));
+		}
+
+		fn synced_shipment(shipment_uid: u64) -> Result<Shipment<T>, DispatchError> {
+			let mut shipment = Self::uid_to_shipment(shipment_uid).ok_or(Error::<T>::ShipmentNotFound)?;
+			if !shipment.status.is_closed() {
+				Self::mirror_item_owner(&mut shipment);
+			}
+			Ok(shipment)
+		}
+
+		// Hands the goods to `new_owner`. Custody and the route are left untouched.
+		fn do_transfer_title(mut shipment: Shipment<T>, new_owner: T::AccountId) -> DispatchResult {
+			let shipment_uid = shipment.uid;
+			Self::log_title(shipment_uid, new_owner.clone())?;
+			T::ShipmentItems::transfer(shipment_uid, &new_owner)?;
+			shipment.beneficial_owner = new_owner.clone();
+			UIDToShipment::<T>::insert(&shipment_uid, &shipment);
+
+			Self::deposit_event(Event::TitleTransferred(shipment_uid, new_owner));
+			Ok(())
 		}
 
 		fn get_and_increment_nonce() -> Vec<u8> {
@@ -333,118 +2626,322 @@
 			nonce.encode()
 		}
 
-		fn get_random_route(origin: T::AccountId, dest: T::AccountId) -> BoundedVec<T::AccountId,T::MaxSize> {
+		// Keys drawn by the chain itself, for rotation. Like all on-chain randomness they can be
+		// derived by anyone following the chain, so they retire a leaked key but do not replace
+		// the check that the handoff comes from the route's next node.
+		fn gen_key() -> [u8; 16] {
+			let nonce = Self::get_and_increment_nonce();
+			(T::KeyRandomNess::random(&nonce).0, <frame_system::Pallet<T>>::block_number(), nonce)
+				.using_encoded(blake2_128)
+		}
+
+		fn gen_seed() -> [u8; 32] {
+			let nonce = Self::get_and_increment_nonce();
+			(T::KeyRandomNess::random(&nonce).0, <frame_system::Pallet<T>>::block_number(), nonce)
+				.using_encoded(blake2_256)
+		}
 
-			let count: u64 = Self::count_for_transit_point();
-			let mut route: BoundedVec<_, _>;
-			let nodes = TransitNodes::<T>::get();
+		fn random_u64() -> u64 {
+			let nonce = Self::get_and_increment_nonce();
+			let random = T::KeyRandomNess::random(&nonce).encode();
+			u64::decode(&mut random.as_ref()).unwrap_or_default()
+		}
+
+		fn can_handle(node: &T::AccountId, handling: HandlingClass) -> bool {
+			handling == HandlingClass::General || Self::node_capabilities(node).contains(&handling)
+		}
+
+		fn has_free_capacity(node: &T::AccountId) -> bool {
+			Self::node_capacity(node).map_or(true, |max| Self::node_holdings(node) < max)
+		}
 
-			if count < 3 {
-				//let route_vec1: BoundedVec<_, _> = bounded_vec![origin.clone(),dest.clone()];
-				//let route_vec2: BoundedVec<_, _> = bounded_vec![dest.clone(),origin.clone()];
+		/// Number of shipments dispatched from `from` to `to` in the current era.
+		pub fn edge_throughput(from: &T::AccountId, to: &T::AccountId) -> u32 {
+			match EdgeThroughput::<T>::get(from, to) {
+				(era_start, count) if era_start == Self::current_era_start() => count,
+				_ => 0,
+			}
+		}
 
-				route = vec![origin.clone(),dest.clone()].try_into().unwrap();
+		fn mutate_edge_throughput(from: &T::AccountId, to: &T::AccountId, f: impl FnOnce(u32) -> u32) {
+			let count = f(Self::edge_throughput(from, to));
+			EdgeThroughput::<T>::insert(from, to, (Self::current_era_start(), count));
+		}
 
-				//RouteVector::<T>::insert(origin.clone(),dest.clone(),vec![origin.clone(),dest.clone()]);
-				//RouteVector::<T>::insert(dest.clone(),origin.clone(),vec![dest.clone(),origin.clone()]);
-			} else if count >= 3 && count <= 5 {
-				let nonce = Self::get_and_increment_nonce();
-				let rv1 = T::KeyRandomNess::random(&nonce).encode();
-				let mut rn1 = u64::decode(&mut rv1.as_ref()).unwrap();
-				let div2: u64 = 2;
+		fn edge_has_capacity(from: &T::AccountId, to: &T::AccountId) -> bool {
+			Self::edge_capacity(from, to).map_or(true, |max| Self::edge_throughput(from, to) < max)
+		}
 
-				let rv2 = rn1 % div2;
+		// Every node but the destination will hold the shipment at some point, and every
+		// leg will carry it once.
+		fn route_has_capacity(route: &[T::AccountId]) -> bool {
+			route[..route.len() - 1].iter().all(Self::has_free_capacity) &&
+				route.windows(2).all(|leg| Self::edge_has_capacity(&leg[0], &leg[1]))
+		}
 
-				match rv2 {
-					0 => {
-						route = vec![origin.clone(),dest.clone()].try_into().unwrap();
-					},
-					1 => {
-						let nonce2 = Self::get_and_increment_nonce();      
-						let mut rv3 = T::KeyRandomNess::random(&nonce2).encode();
-						rn1 = u64::decode(&mut rv3.as_ref()).unwrap();
-						//let count2 = count - 2;
-
-						let mut rv4 = rn1 % count;
-
-						while nodes[rv4 as usize] == origin || nodes[rv4 as usize] == dest {
-							let nonce3 = Self::get_and_increment_nonce();    
-							rv3 = T::KeyRandomNess::random(&nonce3).encode();  
-							rn1 = u64::decode(&mut rv3.as_ref()).unwrap();
-							rv4 = rn1 % count;
-						}
+		// `holder` takes custody of a shipment and sends it towards `next`
+		fn note_dispatch(holder: &T::AccountId, next: &T::AccountId) {
+			NodeHoldings::<T>::mutate(holder, |held| *held = held.saturating_add(1));
+			Self::mutate_edge_throughput(holder, next, |count| count.saturating_add(1));
+		}
 
-						route = vec![origin.clone(),nodes[rv4 as usize].clone(),dest.clone()].try_into().unwrap();
- 					},
-					 _ => {
-						 route = vec![].try_into().unwrap();
-					 }
+		fn note_release(holder: &T::AccountId) {
+			NodeHoldings::<T>::mutate(holder, |held| *held = held.saturating_sub(1));
+		}
+
+		fn bump_topology_version() {
+			TopologyVersion::<T>::mutate(|version| *version = version.wrapping_add(1));
+		}
+
+		fn is_valid_path(from: &T::AccountId, to: &T::AccountId, path: &[T::AccountId]) -> bool {
+			path.first() == Some(from) && path.last() == Some(to) && path.len() >= 2 &&
+				path.len() <= T::MaxSize::get() as usize &&
+				path.windows(2).all(|leg| RouteCosts::<T>::contains_key(&leg[0], &leg[1]))
+		}
+
+		// Checks that `routes` holds exactly one shortest path from `source` to every transit node
+		// reachable from it. Each route must be a valid path, and no route may be beaten by another
+		// route extended by one edge, or by a direct edge. This makes every route's cost the
+		// shortest distance from `source`, and leaves no reachable node without a route. At most
+		// `edges` edges are read, anything more fails with `RouteEdgesUnderstated`.
+		fn check_shortest_paths(source: &T::AccountId, routes: &RouteVectorsOf<T>, edges: u32) -> DispatchResult {
+			let mut costs = BTreeMap::new();
+			for (to, path) in routes.iter() {
+				ensure!(
+					source != to && Self::is_valid_path(source, to, path) &&
+						costs.insert(to.clone(), Self::route_cost(path)? as u64).is_none(),
+					Error::<T>::InvalidRoute);
+			}
+
+			let mut read = 0u32;
+			let within = |to: T::AccountId, cost: u64| {
+				source == &to || costs.get(&to).map_or(false, |shortest| *shortest <= cost)
+			};
+			let ends = sp_std::iter::once((source, 0)).chain(costs.iter().map(|(to, cost)| (to, *cost)));
+			for (end, cost) in ends {
+				for (next, edge) in RouteCosts::<T>::iter_prefix(end) {
+					read = read.saturating_add(1);
+					ensure!(read <= edges, Error::<T>::RouteEdgesUnderstated);
+					ensure!(within(next, cost + edge as u64), Error::<T>::InvalidRoute);
 				}
-			} else  {
-				let nonce = Self::get_and_increment_nonce();
-				let rv1 = T::KeyRandomNess::random(&nonce).encode();
-				let mut rn1 = u64::decode(&mut rv1.as_ref()).unwrap();
-				let div3: u64 = 3;
-
-				let rv2 = rn1 % div3;
-				match rv2 {
-					0 => {
-						route = vec![origin.clone(),dest.clone()].try_into().unwrap();
-					},
-					1 => {
-						let nonce2 = Self::get_and_increment_nonce();      
-						let mut rv3 = T::KeyRandomNess::random(&nonce2).encode();
-						rn1 = u64::decode(&mut rv3.as_ref()).unwrap();
-
-						let mut rv4 = rn1 % count;
-
-						while nodes[rv4 as usize] == origin || nodes[rv4 as usize] == dest {
-							let nonce3 = Self::get_and_increment_nonce();    
-							rv3 = T::KeyRandomNess::random(&nonce3).encode();  
-							rn1 = u64::decode(&mut rv3.as_ref()).unwrap();
-							rv4 = rn1 % count;
-						}
+			}
+			Ok(())
+		}
 
-						route = vec![origin.clone(),nodes[rv4 as usize].clone(),dest.clone()].try_into().unwrap();
+		/// Number of edges `submit_route_vectors` reads to check `routes` out of `source`: the
+		/// edges out of `source` and out of every route's end.
+		pub fn route_vector_edges(source: &T::AccountId, routes: &RouteVectorsOf<T>) -> u32 {
+			sp_std::iter::once(source).chain(routes.iter().map(|(to, _)| to))
+				.map(|node| RouteCosts::<T>::iter_prefix(node).count() as u32)
+				.fold(0, |edges, count| edges.saturating_add(count))
+		}
 
-					},
-					2 => {
-						let nonce2 = Self::get_and_increment_nonce();
-						let mut rv3 = T::KeyRandomNess::random(&nonce2).encode();
-						rn1 = u64::decode(&mut rv3.as_ref()).unwrap();
-						
-
-						let mut rv4 = rn1 % count;
-
-						while nodes[rv4 as usize] == origin || nodes[rv4 as usize] == dest {
-							let nonce3 = Self::get_and_increment_nonce();    
-							rv3 = T::KeyRandomNess::random(&nonce3).encode();
-							rn1 = u64::decode(&mut rv3.as_ref()).unwrap();  
-							rv4 = rn1 % count;
-						}
+		/// Whether `call` is a handoff by the node `shipment_uid` is being handed to, and that
+		/// node has feeless handoffs left this era.
+		pub fn is_feeless_handoff(who: &T::AccountId, call: &<T as frame_system::Config>::Call) -> bool
+		where
+			<T as frame_system::Config>::Call: IsSubType<Call<T>>,
+		{
+			let shipment_uid = match call.is_sub_type() {
+				Some(Call::update_shipment { shipment_uid, .. }) => *shipment_uid,
+				_ => return false,
+			};
+			let receiver = Self::uid_to_shipment(shipment_uid)
+				.filter(|shipment| shipment.owner_index > 0)
+				.and_then(|shipment| shipment.route.get(shipment.owner_index as usize).cloned());
+			receiver.as_ref() == Some(who) && Self::feeless_handoffs_used(who) < T::MaxFeelessHandoffs::get()
+		}
+
+		pub(crate) fn note_feeless_handoff(node: &T::AccountId) {
+			let used = Self::feeless_handoffs_used(node).saturating_add(1);
+			FeelessHandoffs::<T>::insert(node, (Self::current_era_start(), used));
+		}
+
+		fn feeless_handoffs_used(node: &T::AccountId) -> u32 {
+			match Self::feeless_handoffs(node) {
+				(era_start, used) if era_start == Self::current_era_start() => used,
+				_ => 0,
+			}
+		}
+
+		pub(crate) fn current_era_start() -> T::BlockNumber {
+			let now = <frame_system::Pallet<T>>::block_number();
+			let era_length = T::EraLength::get();
+			if era_length.is_zero() {
+				return Zero::zero()
+			}
+			now - now % era_length
+		}
+
+		/// Checks an `update_shipment` of `shipment_uid` by `who` against the shipment's current
+		/// state, without applying it. Returns the hop the shipment is handed to.
+		pub fn check_handoff(
+			who: &T::AccountId,
+			shipment_uid: u64,
+			proof: &HandoffProofOf<T>,
+		) -> Result<u8, InvalidTransaction> {
+			let shipment = Self::uid_to_shipment(shipment_uid).ok_or(InvalidTransaction::Call)?;
+			ensure!(shipment.owner_index > 0, InvalidTransaction::Stale);
+			ensure!(shipment.route.get(shipment.owner_index as usize) == Some(who), InvalidTransaction::BadSigner);
+			Self::verify_handoff(&shipment, proof.clone()).map_err(|err| {
+				match err == DispatchError::from(Error::<T>::HandoffExpired) {
+					true => InvalidTransaction::Stale,
+					false => InvalidTransaction::BadProof,
+				}
+			})?;
+			Ok(shipment.owner_index)
+		}
+
+		/// Checks the signatures of a handoff voucher against its shipment's route, without
+		/// applying it. Vouchers for hops past the shipment's current one pass, as they may follow
+		/// vouchers not applied yet. Returns the shipment's current hop.
+		pub fn check_voucher(voucher: &HandoffVoucherOf<T>) -> Result<u8, InvalidTransaction> {
+			let shipment = Self::uid_to_shipment(voucher.shipment_uid).ok_or(InvalidTransaction::Call)?;
+			ensure!(shipment.handoff_mode == HandoffMode::MutualSignature, InvalidTransaction::Call);
+			ensure!(shipment.owner_index > 0 && voucher.hop >= shipment.owner_index, InvalidTransaction::Stale);
+			ensure!((voucher.hop as usize) < shipment.route.len(), InvalidTransaction::Call);
+
+			let payload = Self::voucher_payload(voucher.shipment_uid, voucher.hop, voucher.moment);
+			ensure!(
+				!ConsumedVouchers::<T>::contains_key(voucher.shipment_uid, T::Hashing::hash(&payload)),
+				InvalidTransaction::Stale);
+			let hop = voucher.hop as usize;
+			ensure!(
+				voucher.releaser.verify(&payload[..], &shipment.route[hop - 1]) &&
+					voucher.receiver.verify(&payload[..], &shipment.route[hop]),
+				InvalidTransaction::BadProof);
+			Ok(shipment.owner_index)
+		}
+
+		/// Account holding the insurance pool.
+		pub fn insurance_pool() -> T::AccountId {
+			T::InsurancePalletId::get().into_account()
+		}
 
-						let nonce5 = Self::get_and_increment_nonce();    
-						rv3 = T::KeyRandomNess::random(&nonce5).encode();
-						rn1 = u64::decode(&mut rv3.as_ref()).unwrap();  
-						let mut rv5 = rn1 % count;
+		/// All-pairs shortest paths over `RouteCosts` (Floyd-Warshall), grouped by source node.
+		/// Run by the offchain worker, the routes out of each node are submitted back into
+		/// `RouteVector` separately.
+		pub fn compute_route_vectors() -> Vec<(T::AccountId, RouteVectorsOf<T>)> {
+			let nodes = Self::transit_nodes();
+			let n = nodes.len();
+
+			let mut dist: Vec<Vec<Option<u64>>> = vec![vec![None; n]; n];
+			let mut next: Vec<Vec<usize>> = vec![vec![0; n]; n];
+			for i in 0..n {
+				for j in 0..n {
+					if i == j {
+						continue;
+					}
+					if let Some(cost) = Self::route_costs(&nodes[i], &nodes[j]) {
+						dist[i][j] = Some(cost as u64);
+						next[i][j] = j;
+					}
+				}
+			}
 
-						while nodes[rv5 as usize] == origin || nodes[rv5 as usize] == dest || rv5 == rv4 {
-							let nonce6 = Self::get_and_increment_nonce();    
-							rv3 = T::KeyRandomNess::random(&nonce6).encode();
-							rn1 = u64::decode(&mut rv3.as_ref()).unwrap();  
-							rv5 = rn1 % count;
+			for k in 0..n {
+				for i in 0..n {
+					if let Some(to_k) = dist[i][k] {
+						for j in 0..n {
+							if let Some(from_k) = dist[k][j] {
+								let through_k = to_k.saturating_add(from_k);
+								if i != j && dist[i][j].map_or(true, |direct| through_k < direct) {
+									dist[i][j] = Some(through_k);
+									next[i][j] = next[i][k];
+								}
+							}
 						}
+					}
+				}
+			}
 
-						route = vec![origin.clone(),nodes[rv4 as usize].clone(),nodes[rv5 as usize].clone(),dest.clone()].try_into().unwrap();
+			let mut vectors = Vec::new();
+			for i in 0..n {
+				let mut routes: Vec<(T::AccountId, BoundedVec<T::AccountId, T::MaxSize>)> = Vec::new();
+				for j in 0..n {
+					if i == j || dist[i][j].is_none() {
+						continue;
+					}
+					let mut path = vec![nodes[i].clone()];
+					let mut at = i;
+					while at != j {
+						at = next[at][j];
+						path.push(nodes[at].clone());
+					}
+					if let Ok(path) = path.try_into() {
+						routes.push((nodes[j].clone(), path));
+					}
+				}
+				// There are at most `MaxSize` transit nodes, so every path and route fits
+				vectors.push((nodes[i].clone(), routes.try_into().unwrap_or_default()));
+			}
+			vectors
+		}
 
-					},
-					_ => {
-						route = vec![].try_into().unwrap();
+		// Prefer the shortest path precomputed by the offchain worker, as long as it matches the
+		// current topology and suits the shipment. Otherwise fall back to a random route.
+		fn find_route(
+			origin: T::AccountId,
+			dest: T::AccountId,
+			handling: HandlingClass
+		) -> Result<BoundedVec<T::AccountId,T::MaxSize>, Error<T>> {
+
+			if Self::route_vector_version() == Self::topology_version() {
+				if let Some(path) = Self::route_vec(&origin, &dest) {
+					if path.iter().all(|node| Self::can_handle(node, handling)) && Self::route_has_capacity(&path) {
+						if let Ok(route) = path.try_into() {
+							return Ok(route)
+						}
 					}
 				}
 			}
 
-			route
+			Self::get_random_route(origin, dest, handling)
+		}
+
+		fn get_random_route(
+			origin: T::AccountId,
+			dest: T::AccountId,
+			handling: HandlingClass
+		) -> Result<BoundedVec<T::AccountId,T::MaxSize>, Error<T>> {
+
+			ensure!(Self::can_handle(&origin, handling) && Self::can_handle(&dest, handling), Error::<T>::NoCompliantRoute);
+
+			let count: u64 = Self::count_for_transit_point();
+			let max_stops: u64 = match count {
+				0..=2 => 0,
+				3..=5 => 1,
+				_ => 2,
+			};
+
+			// Only nodes able to handle the shipment and with room to hold it are considered
+			// as intermediate stops
+			let mut candidates: Vec<T::AccountId> = Self::transit_nodes()
+				.into_iter()
+				.filter(|node| {
+					*node != origin && *node != dest &&
+						Self::can_handle(node, handling) && Self::has_free_capacity(node)
+				})
+				.collect();
+
+			let stops = Self::random_u64() % (max_stops + 1);
+			let mut route = vec![origin.clone()];
+			for _ in 0..stops {
+				if candidates.is_empty() {
+					break;
+				}
+				let index = Self::random_u64() % candidates.len() as u64;
+				route.push(candidates.swap_remove(index as usize));
+			}
+			route.push(dest.clone());
+
+			// Fall back to the direct route if a leg of the random one is saturated
+			if !Self::route_has_capacity(&route) {
+				route = vec![origin, dest];
+				ensure!(Self::route_has_capacity(&route), Error::<T>::RouteCapacityExhausted);
+			}
+
+			route.try_into().map_err(|_| Error::<T>::InvalidRoute)
 		}
 
 		// fn set_fees() {}
@@ -0,0 +1,239 @@
+//! Storage migrations for the asset tracking pallet.
+
+pub mod v1 {
+	//! Moves shipments from the original layout, with three states and no handling class,
+	//! consignee or handoff mode, to the full `ShipmentStatus` lifecycle. State the original
+	//! layout did not keep is backfilled: delivered shipments are closed, and shipments in
+	//! flight get a custody log and count against their holder's capacity. Handoff keys were
+	//! stored in the clear, so anyone may have read them. They are revoked, and the creator
+	//! has to reissue them.
+
+	use crate::{
+		pallet::{CustodyLog, NodeHoldings, ShipmentClosedAt, UIDToKey, UIDToShipment},
+		Config, CustodyEntry, HandlingClass, HandoffMode, Pallet, ShipmentStatus,
+	};
+	use codec::{Decode, Encode};
+	use frame_support::{
+		pallet_prelude::*,
+		storage::unhashed,
+		traits::{Currency, GetStorageVersion, StorageVersion},
+		BoundedVec,
+	};
+	use sp_std::vec::Vec;
+
+	type BalanceOf<T> =
+		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+	#[derive(Clone, Encode, Decode, PartialEq, Copy, RuntimeDebug)]
+	pub enum OldShipmentStatus {
+		InTransit,
+		Delivered,
+		Failed,
+	}
+
+	impl From<OldShipmentStatus> for ShipmentStatus {
+		fn from(old: OldShipmentStatus) -> Self {
+			match old {
+				OldShipmentStatus::InTransit => ShipmentStatus::InTransit,
+				OldShipmentStatus::Delivered => ShipmentStatus::Delivered,
+				// A failed shipment was stuck wherever it failed. It is held there so that it
+				// can still be returned to its sender.
+				OldShipmentStatus::Failed => ShipmentStatus::OnHold,
+			}
+		}
+	}
+
+	#[derive(Encode, Decode)]
+	pub struct OldShipment<T: Config> {
+		pub creator: T::AccountId,
+		pub fees: Option<BalanceOf<T>>,
+		pub owner_index: u8,
+		pub route: BoundedVec<T::AccountId, T::MaxSize>,
+		pub destination: T::AccountId,
+		pub uid: u64,
+		pub status: OldShipmentStatus,
+	}
+
+	pub fn migrate<T: Config>() -> Weight {
+		if Pallet::<T>::on_chain_storage_version() != 0 {
+			return T::DbWeight::get().reads(1)
+		}
+
+		// Shipments are written in the v1 layout rather than as the current `Shipment`, so that
+		// later migrations pick them up from there. They predate handling classes, consignees
+		// and mutual signatures.
+		let now = <frame_system::Pallet<T>>::block_number();
+		let moment = <pallet_timestamp::Pallet<T>>::get();
+		let mut translated: Weight = 0;
+		let mut backfilled: Weight = 0;
+		for uid in UIDToShipment::<T>::iter_keys().collect::<Vec<_>>() {
+			let key = UIDToShipment::<T>::hashed_key_for(uid);
+			if let Some(old) = unhashed::get::<OldShipment<T>>(&key) {
+				translated += 1;
+				let hops = (old.owner_index as usize).min(old.route.len());
+				match old.status {
+					// Delivered shipments become reapable once the retention period has passed
+					OldShipmentStatus::Delivered => {
+						ShipmentClosedAt::<T>::insert(uid, now);
+						backfilled += 1;
+					},
+					// The original layout kept no history, so every hop up to the current
+					// holder is logged as of the upgrade
+					_ if hops > 0 => {
+						let _ = CustodyLog::<T>::try_mutate(uid, |log| {
+							old.route[..hops].iter().enumerate().try_for_each(|(hop, handler)| {
+								log.try_push(CustodyEntry { handler: handler.clone(), block: now, moment, hop: hop as u8 })
+							})
+						});
+						NodeHoldings::<T>::mutate(&old.route[hops - 1], |held| *held = held.saturating_add(1));
+						backfilled += 2;
+					},
+					_ => {},
+				}
+				unhashed::put(&key, &super::v2::OldShipment::<T> {
+					creator: old.creator,
+					fees: old.fees,
+					owner_index: old.owner_index,
+					route: old.route,
+					destination: old.destination,
+					uid: old.uid,
+					status: old.status.into(),
+					handling: HandlingClass::General,
+					consignee: None,
+					handoff_mode: HandoffMode::SharedKey,
+				});
+			}
+		}
+
+		let mut keys: Weight = 0;
+		UIDToKey::<T>::translate::<[u8; 16], _>(|_, _| {
+			keys += 1;
+			None
+		});
+
+		StorageVersion::new(1).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(translated + backfilled + keys + 4, translated + backfilled + keys + 1)
+	}
+}
+
+pub mod v2 {
+	//! Separates ownership of the goods from custody: every shipment gets a
+	//! `beneficial_owner` and a title history starting with it.
+
+	use crate::{
+		pallet::{ShipmentDeposits, TitleHistory, UIDToShipment},
+		Config, HandlingClass, HandoffMode, Pallet, Shipment, ShipmentStatus, TitleEntry,
+	};
+	use codec::{Decode, Encode};
+	use frame_support::{
+		pallet_prelude::*,
+		traits::{Currency, GetStorageVersion, StorageVersion},
+		BoundedVec,
+	};
+
+	type BalanceOf<T> =
+		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+	#[derive(Encode, Decode)]
+	pub struct OldShipment<T: Config> {
+		pub creator: T::AccountId,
+		pub fees: Option<BalanceOf<T>>,
+		pub owner_index: u8,
+		pub route: BoundedVec<T::AccountId, T::MaxSize>,
+		pub destination: T::AccountId,
+		pub uid: u64,
+		pub status: ShipmentStatus,
+		pub handling: HandlingClass,
+		pub consignee: Option<T::AccountId>,
+		pub handoff_mode: HandoffMode,
+	}
+
+	pub fn migrate<T: Config>() -> Weight {
+		if Pallet::<T>::on_chain_storage_version() != 1 {
+			return T::DbWeight::get().reads(1)
+		}
+
+		let now = <frame_system::Pallet<T>>::block_number();
+		let mut translated: Weight = 0;
+		UIDToShipment::<T>::translate::<OldShipment<T>, _>(|uid, old| {
+			translated += 1;
+			// Whoever paid for the shipment owned the goods so far
+			let beneficial_owner = ShipmentDeposits::<T>::get(uid)
+				.map(|(payer, _)| payer)
+				.unwrap_or_else(|| old.creator.clone());
+			let _ = TitleHistory::<T>::try_mutate(uid, |history| {
+				history.try_push(TitleEntry { owner: beneficial_owner.clone(), block: now })
+			});
+			Some(Shipment {
+				creator: old.creator,
+				beneficial_owner,
+				fees: old.fees,
+				owner_index: old.owner_index,
+				route: old.route,
+				destination: old.destination,
+				uid: old.uid,
+				status: old.status,
+				handling: old.handling,
+				consignee: old.consignee,
+				handoff_mode: old.handoff_mode,
+			})
+		});
+
+		StorageVersion::new(2).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(2 * translated + 1, 2 * translated + 1)
+	}
+}
+
+pub mod v3 {
+	//! Sets up shipment items on chains that started before they existed, so the collection
+	//! they are kept in can't be claimed by anyone else first.
+
+	use crate::{nft::ShipmentItems, Config, Pallet};
+	use frame_support::{
+		pallet_prelude::*,
+		traits::{GetStorageVersion, StorageVersion},
+	};
+
+	pub fn migrate<T: Config>() -> Weight {
+		if Pallet::<T>::on_chain_storage_version() != 2 {
+			return T::DbWeight::get().reads(1)
+		}
+
+		// If the collection was claimed already, minting fails loudly until it is sorted out
+		let _ = T::ShipmentItems::setup();
+
+		StorageVersion::new(3).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(3, 3)
+	}
+}
+
+pub mod v4 {
+	//! Tags edge throughput counters with the era they were counted in, so they no longer have
+	//! to be cleared when an era ends.
+
+	use crate::{pallet::EdgeThroughput, Config, Pallet};
+	use frame_support::{
+		pallet_prelude::*,
+		traits::{GetStorageVersion, StorageVersion},
+	};
+
+	pub fn migrate<T: Config>() -> Weight {
+		if Pallet::<T>::on_chain_storage_version() != 3 {
+			return T::DbWeight::get().reads(1)
+		}
+
+		let era_start = Pallet::<T>::current_era_start();
+		let mut translated: Weight = 0;
+		EdgeThroughput::<T>::translate::<u32, _>(|_, _, count| {
+			translated += 1;
+			Some((era_start, count))
+		});
+
+		StorageVersion::new(4).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+	}
+}
@@ -1,11 +1,15 @@
 use crate as pallet_template;
 use frame_support::{
-	traits::{ConstU16, ConstU32, ConstU64, ConstU128}};
+	parameter_types,
+	traits::{ConstU16, ConstU32, ConstU64, ConstU8, ConstU128, Currency, OnUnbalanced},
+	weights::IdentityFee,
+	PalletId};
 use frame_system as system;
 use sp_core::H256;
 use sp_runtime::{
-	testing::Header,
+	testing::{Header, TestSignature, TestXt, UintAuthorityId},
 	traits::{BlakeTwo256, IdentityLookup},
+	Permill,
 };
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
@@ -22,14 +26,17 @@ frame_support::construct_runtime!(
 	{
 		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
 		Balances: pallet_balances,
-		AssetTracking: pallet_template::{Pallet, Call, Storage, Event<T>},
+		AssetTracking: pallet_template::{Pallet, Call, Config, Storage, Event<T>, ValidateUnsigned},
 		RandomnessCollectiveFlip: pallet_randomness_collective_flip,
+		Timestamp: pallet_timestamp,
+		Uniques: pallet_uniques,
+		TransactionPayment: pallet_transaction_payment,
 	}
 );
 
 impl system::Config for Test {
 	type BaseCallFilter = frame_support::traits::Everything;
-	type BlockWeights = ();
+	type BlockWeights = BlockWeights;
 	type BlockLength = ();
 	type DbWeight = ();
 	type Origin = Origin;
@@ -54,11 +61,60 @@ impl system::Config for Test {
 	type MaxConsumers = frame_support::traits::ConstU32<16>;
 }
 
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Test
+where
+	Call: From<C>,
+{
+	type OverarchingCall = Call;
+	type Extrinsic = TestXt<Call, ()>;
+}
+
 impl pallet_template::Config for Test {
 	type Event = Event;
 	type Currency = Balances;
 	type KeyRandomNess = RandomnessCollectiveFlip;
 	type MaxSize = frame_support::traits::ConstU32<100>;
+	type EraLength = ConstU64<10>;
+	type AutoConfirmPeriod = ConstU64<5>;
+	type ShipmentDeposit = ConstU128<100>;
+	type RetentionPeriod = ConstU64<20>;
+	type MaxArchivedShipments = ConstU32<2>;
+	type Signature = TestSignature;
+	type Signer = UintAuthorityId;
+	type HandoffValidity = ConstU64<10>;
+	type MaxHandoffBatch = ConstU32<4>;
+	type KeyLifetime = ConstU64<15>;
+	type FeePerCostUnit = FeePerCostUnit;
+	type ShipmentItems = pallet_template::nft::UniquesItems<Test, ConstU32<0>, TrackerPalletId>;
+	type InsurancePalletId = InsurancePalletId;
+	type InsurancePremium = InsurancePremium;
+	type MaxClaimShare = MaxClaimShare;
+	type FeeDestination = ToTreasury;
+	type TreasuryShare = TreasuryShare;
+	type SponsorshipPeriod = ConstU64<30>;
+	type MaxFeelessHandoffs = ConstU32<1>;
+}
+
+/// Account the treasury's share of shipment fees is paid to.
+pub const TREASURY: u64 = 10;
+
+pub struct ToTreasury;
+
+impl OnUnbalanced<pallet_balances::NegativeImbalance<Test>> for ToTreasury {
+	fn on_nonzero_unbalanced(amount: pallet_balances::NegativeImbalance<Test>) {
+		Balances::resolve_creating(&TREASURY, amount);
+	}
+}
+
+parameter_types! {
+	// No base fee, so transaction fees only depend on the length and weight of a call
+	pub BlockWeights: frame_system::limits::BlockWeights = frame_system::limits::BlockWeights::simple_max(1024);
+	pub const TrackerPalletId: PalletId = PalletId(*b"trk/ship");
+	pub const InsurancePalletId: PalletId = PalletId(*b"trk/insr");
+	pub const InsurancePremium: Permill = Permill::from_percent(5);
+	pub const MaxClaimShare: Permill = Permill::from_percent(50);
+	pub const TreasuryShare: Permill = Permill::from_percent(10);
+	pub static FeePerCostUnit: Balance = 1;
 }
 
 impl pallet_balances::Config for Test {
@@ -77,7 +133,46 @@ impl pallet_balances::Config for Test {
 
 impl pallet_randomness_collective_flip::Config for Test {}
 
+impl pallet_timestamp::Config for Test {
+	type Moment = u64;
+	type OnTimestampSet = ();
+	type MinimumPeriod = ConstU64<1>;
+	type WeightInfo = ();
+}
+
+impl pallet_uniques::Config for Test {
+	type Event = Event;
+	type ClassId = u32;
+	type InstanceId = u64;
+	type Currency = Balances;
+	type ForceOrigin = frame_system::EnsureRoot<u64>;
+	type ClassDeposit = ConstU128<0>;
+	type InstanceDeposit = ConstU128<0>;
+	type MetadataDepositBase = ConstU128<0>;
+	type AttributeDepositBase = ConstU128<0>;
+	type DepositPerByte = ConstU128<0>;
+	type StringLimit = ConstU32<50>;
+	type KeyLimit = ConstU32<32>;
+	type ValueLimit = ConstU32<64>;
+	type WeightInfo = ();
+}
+
+impl pallet_transaction_payment::Config for Test {
+	type OnChargeTransaction = pallet_transaction_payment::CurrencyAdapter<Balances, ()>;
+	type OperationalFeeMultiplier = ConstU8<5>;
+	type WeightToFee = IdentityFee<Balance>;
+	type LengthToFee = IdentityFee<Balance>;
+	type FeeMultiplierUpdate = ();
+}
+
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
-	system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
+	let mut storage = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	pallet_balances::GenesisConfig::<Test> {
+		balances: (1..=10).map(|account| (account, 10_000)).collect(),
+	}
+	.assimilate_storage(&mut storage)
+	.unwrap();
+	pallet_template::GenesisConfig::default().assimilate_storage::<Test>(&mut storage).unwrap();
+	storage.into()
 }
@@ -0,0 +1,171 @@
+//! Shipments as non-fungible items, so goods in transit can be traded and used as collateral
+//! with standard NFT tooling.
+
+use crate::ShipmentStatus;
+use codec::Encode;
+use frame_support::{dispatch::DispatchResult, traits::Get, BoundedVec, PalletId};
+use sp_runtime::{
+	traits::{AccountIdConversion, StaticLookup},
+	DispatchError,
+};
+use sp_std::marker::PhantomData;
+
+/// Attribute key under which the SCALE encoded `ShipmentStatus` of a shipment is kept.
+pub const STATUS_ATTRIBUTE: &[u8] = b"status";
+
+/// Mirrors shipments into non-fungible items. `()` keeps shipments off any NFT pallet.
+pub trait ShipmentItems<AccountId> {
+	/// Prepare to keep shipment items. Called at genesis and when the pallet is upgraded.
+	fn setup() -> DispatchResult;
+	/// Mint the item of a new shipment to the owner of its goods.
+	fn mint(shipment_uid: u64, owner: &AccountId) -> DispatchResult;
+	/// Record the shipment's new status on its item.
+	fn set_status(shipment_uid: u64, status: ShipmentStatus) -> DispatchResult;
+	/// Move the item along with the title to the goods.
+	fn transfer(shipment_uid: u64, to: &AccountId) -> DispatchResult;
+	/// Current owner of the item, which may have been traded outside the pallet.
+	fn owner(shipment_uid: u64) -> Option<AccountId>;
+	/// Stop the item from being traded once the shipment is closed.
+	fn lock(shipment_uid: u64) -> DispatchResult;
+	/// Remove the item of a reaped shipment.
+	fn burn(shipment_uid: u64) -> DispatchResult;
+}
+
+impl<AccountId> ShipmentItems<AccountId> for () {
+	fn setup() -> DispatchResult {
+		Ok(())
+	}
+
+	fn mint(_: u64, _: &AccountId) -> DispatchResult {
+		Ok(())
+	}
+
+	fn set_status(_: u64, _: ShipmentStatus) -> DispatchResult {
+		Ok(())
+	}
+
+	fn transfer(_: u64, _: &AccountId) -> DispatchResult {
+		Ok(())
+	}
+
+	fn owner(_: u64) -> Option<AccountId> {
+		None
+	}
+
+	fn lock(_: u64) -> DispatchResult {
+		Ok(())
+	}
+
+	fn burn(_: u64) -> DispatchResult {
+		Ok(())
+	}
+}
+
+/// Keeps one `pallet_uniques` item per shipment, numbered by shipment uid, in the collection
+/// `Collection`.
+///
+/// The collection is created at genesis, or on first use, with the account of `Tracker` as its
+/// owner, admin and freezer, and without deposits. `pallet_uniques`' `ForceOrigin` must accept
+/// Root. Items can be traded freely while their shipment is open; the pallet takes whoever
+/// holds an item as the beneficial owner of the goods. Items are frozen once the shipment is
+/// closed.
+pub struct UniquesItems<T, Collection, Tracker>(PhantomData<(T, Collection, Tracker)>);
+
+impl<T, Collection, Tracker> UniquesItems<T, Collection, Tracker>
+where
+	T: pallet_uniques::Config,
+	T::InstanceId: From<u64>,
+	Collection: Get<T::ClassId>,
+	Tracker: Get<PalletId>,
+{
+	fn tracker() -> T::AccountId {
+		Tracker::get().into_account()
+	}
+
+	fn origin() -> T::Origin {
+		frame_system::RawOrigin::Signed(Self::tracker()).into()
+	}
+
+	// Anyone can create a collection under any free id, so a collection not owned by the tracker
+	// can't be used
+	fn ensure_collection() -> DispatchResult {
+		let collection = Collection::get();
+		match pallet_uniques::Pallet::<T>::class_owner(collection) {
+			Some(owner) if owner == Self::tracker() => Ok(()),
+			Some(_) => Err(DispatchError::Other("shipment collection is owned by another account")),
+			None => pallet_uniques::Pallet::<T>::force_create(
+				frame_system::RawOrigin::Root.into(),
+				collection,
+				T::Lookup::unlookup(Self::tracker()),
+				true,
+			),
+		}
+	}
+
+	// Shipments created before the collection was set up have no item to keep in sync
+	fn item(shipment_uid: u64) -> Option<T::InstanceId> {
+		let item = T::InstanceId::from(shipment_uid);
+		pallet_uniques::Pallet::<T>::owner(Collection::get(), item).map(|_| item)
+	}
+}
+
+impl<T, Collection, Tracker> ShipmentItems<T::AccountId> for UniquesItems<T, Collection, Tracker>
+where
+	T: pallet_uniques::Config,
+	T::InstanceId: From<u64>,
+	Collection: Get<T::ClassId>,
+	Tracker: Get<PalletId>,
+{
+	fn setup() -> DispatchResult {
+		Self::ensure_collection()
+	}
+
+	fn mint(shipment_uid: u64, owner: &T::AccountId) -> DispatchResult {
+		Self::ensure_collection()?;
+		let collection = Collection::get();
+		pallet_uniques::Pallet::<T>::mint(
+			Self::origin(),
+			collection,
+			shipment_uid.into(),
+			T::Lookup::unlookup(owner.clone()),
+		)
+	}
+
+	fn set_status(shipment_uid: u64, status: ShipmentStatus) -> DispatchResult {
+		let item = match Self::item(shipment_uid) {
+			Some(item) => item,
+			None => return Ok(()),
+		};
+		let key = BoundedVec::try_from(STATUS_ATTRIBUTE.to_vec())
+			.map_err(|_| DispatchError::Other("KeyLimit too small for the status attribute"))?;
+		let value = BoundedVec::try_from(status.encode())
+			.map_err(|_| DispatchError::Other("ValueLimit too small for the status attribute"))?;
+		pallet_uniques::Pallet::<T>::set_attribute(Self::origin(), Collection::get(), Some(item), key, value)
+	}
+
+	fn transfer(shipment_uid: u64, to: &T::AccountId) -> DispatchResult {
+		let item = match Self::item(shipment_uid) {
+			Some(item) => item,
+			None => return Ok(()),
+		};
+		pallet_uniques::Pallet::<T>::transfer(Self::origin(), Collection::get(), item, T::Lookup::unlookup(to.clone()))
+	}
+
+	fn owner(shipment_uid: u64) -> Option<T::AccountId> {
+		pallet_uniques::Pallet::<T>::owner(Collection::get(), shipment_uid.into())
+	}
+
+	fn lock(shipment_uid: u64) -> DispatchResult {
+		match Self::item(shipment_uid) {
+			Some(item) => pallet_uniques::Pallet::<T>::freeze(Self::origin(), Collection::get(), item),
+			None => Ok(()),
+		}
+	}
+
+	fn burn(shipment_uid: u64) -> DispatchResult {
+		match Self::item(shipment_uid) {
+			Some(item) => pallet_uniques::Pallet::<T>::burn(Self::origin(), Collection::get(), item, None),
+			None => Ok(()),
+		}
+	}
+}
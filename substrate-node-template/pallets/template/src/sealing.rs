@@ -0,0 +1,75 @@
+//! Sealing of handoff keys to the next holder of a shipment.
+//!
+//! A sealed key can only be opened by the node holding the ed25519 key registered on chain
+//! under `KEY_TYPE`. Sealing is ECIES over the x25519 form of that key: an ephemeral key is
+//! agreed with the recipient, and the shared secret is expanded with blake2 into a keystream
+//! and a MAC key. Used by the node to seal and open keys, and by the pallet to seal keys it
+//! rotates itself.
+
+use crate::SealedKey;
+use curve25519_dalek::{
+	constants::X25519_BASEPOINT,
+	edwards::CompressedEdwardsY,
+	montgomery::MontgomeryPoint,
+	scalar::Scalar,
+};
+use sha2::{Digest, Sha512};
+use sp_io::hashing::{blake2_128, blake2_256};
+use sp_std::vec::Vec;
+
+/// Encrypt `key` to the holder of the ed25519 key `recipient`, with the ephemeral key derived
+/// from `ephemeral_seed`. The seed must never be reused.
+///
+/// Returns `None` if `recipient` is not a valid curve point.
+pub fn seal(recipient: &[u8; 32], ephemeral_seed: &[u8; 32], key: &[u8; 16]) -> Option<SealedKey> {
+	let recipient = CompressedEdwardsY(*recipient).decompress()?.to_montgomery();
+	let secret = x25519_secret(ephemeral_seed);
+	let ephemeral = (&X25519_BASEPOINT * &secret).to_bytes();
+	let shared = (&recipient * &secret).to_bytes();
+
+	let (keystream, mac_key) = derive_keys(&shared, &ephemeral, &recipient.to_bytes());
+	let mut ciphertext = [0u8; 16];
+	for (i, byte) in ciphertext.iter_mut().enumerate() {
+		*byte = key[i] ^ keystream[i];
+	}
+	let tag = blake2_128(&[&mac_key[..], &ciphertext[..]].concat());
+
+	Some(SealedKey { ephemeral, ciphertext, tag })
+}
+
+/// Decrypt a key sealed to the ed25519 key with seed `seed`. Returns `None` if it was sealed to
+/// another key or tampered with.
+pub fn open(seed: &[u8; 32], sealed: &SealedKey) -> Option<[u8; 16]> {
+	let secret = x25519_secret(seed);
+	let recipient = (&X25519_BASEPOINT * &secret).to_bytes();
+	let shared = (&MontgomeryPoint(sealed.ephemeral) * &secret).to_bytes();
+
+	let (keystream, mac_key) = derive_keys(&shared, &sealed.ephemeral, &recipient);
+	if blake2_128(&[&mac_key[..], &sealed.ciphertext[..]].concat()) != sealed.tag {
+		return None
+	}
+
+	let mut key = [0u8; 16];
+	for (i, byte) in key.iter_mut().enumerate() {
+		*byte = sealed.ciphertext[i] ^ keystream[i];
+	}
+	Some(key)
+}
+
+// The x25519 scalar matching an ed25519 seed, as in RFC 8032 key generation.
+fn x25519_secret(seed: &[u8; 32]) -> Scalar {
+	let hash = Sha512::digest(seed);
+	let mut bytes = [0u8; 32];
+	bytes.copy_from_slice(&hash[..32]);
+	bytes[0] &= 248;
+	bytes[31] &= 127;
+	bytes[31] |= 64;
+	Scalar::from_bits(bytes)
+}
+
+fn derive_keys(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+	let context: Vec<u8> = [&shared[..], &ephemeral[..], &recipient[..]].concat();
+	let keystream = blake2_256(&[&b"trak-enc"[..], &context[..]].concat());
+	let mac_key = blake2_256(&[&b"trak-mac"[..], &context[..]].concat());
+	(keystream, mac_key)
+}
@@ -1,5 +1,24 @@
-use crate::{mock::*, Error};
-use frame_support::{assert_noop, assert_ok,bounded_vec};
+use crate::{crypto, extensions::{CheckHandoff, FeelessHandoff}, geo::{self, Coordinates}, mock::*, nft::STATUS_ATTRIBUTE, sealing, CustodyEntry, Error, HandlingClass, HandoffMode, HandoffProof, HandoffVoucher, SealedKey, ShipmentStatus, TransportMode};
+use codec::Encode;
+use frame_support::{assert_noop, assert_ok,bounded_vec, storage::unhashed, traits::{tokens::nonfungibles::Inspect, GetStorageVersion, Hooks, StorageVersion}, weights::DispatchInfo};
+use pallet_transaction_payment::ChargeTransactionPayment;
+use sp_core::{Pair, H256};
+use sp_runtime::{testing::TestSignature, traits::{BlakeTwo256, Dispatchable, Hash, SignedExtension}, transaction_validity::{InvalidTransaction, ValidTransaction}};
+
+const KEY: [u8; 16] = [1; 16];
+
+fn commitment(key: [u8; 16]) -> H256 {
+	BlakeTwo256::hash(&key)
+}
+
+// Submits the routes out of every transit node, as the offchain worker does
+fn submit_route_vectors() {
+	let version = AssetTracking::topology_version();
+	for (source,routes) in AssetTracking::compute_route_vectors() {
+		let edges = AssetTracking::route_vector_edges(&source,&routes);
+		assert_ok!(AssetTracking::submit_route_vectors(Origin::none(),version,source,routes,edges));
+	}
+}
 
 #[test]
 fn it_creates_transit_node() {
@@ -52,6 +71,1083 @@ fn it_adds_more_nodes() {
 	});
 }
 
+#[test]
+fn it_computes_great_circle_distance() {
+	let london = Coordinates { lat: 51_507_400, lon: -127_800 };
+	let paris = Coordinates { lat: 48_856_600, lon: 2_352_200 };
+	// London to Paris is roughly 343.5km
+	let distance = geo::great_circle_distance(&london, &paris);
+	assert!(distance > 343_000 && distance < 344_000);
+	assert_eq!(geo::great_circle_distance(&london, &london), 0);
+}
+
+#[test]
+fn it_derives_edge_costs_from_locations() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		// Edge mode needs both locations
+		assert_noop!(AssetTracking::set_edge_mode(Origin::root(),1,2,TransportMode::Road),
+		Error::<Test>::LocationNotFound);
+		assert_noop!(AssetTracking::set_node_location(Origin::root(),1,Coordinates { lat: 91_000_000, lon: 0 }),
+		Error::<Test>::InvalidCoordinates);
+		assert_ok!(AssetTracking::set_node_location(Origin::root(),1,Coordinates { lat: 51_507_400, lon: -127_800 }));
+		assert_ok!(AssetTracking::set_node_location(Origin::root(),2,Coordinates { lat: 48_856_600, lon: 2_352_200 }));
+		// Cost is the distance in km times the mode multiplier
+		assert_ok!(AssetTracking::set_edge_mode(Origin::root(),1,2,TransportMode::Road));
+		assert_eq!(AssetTracking::route_costs(1,2),Some(343));
+		assert_eq!(AssetTracking::route_costs(2,1),Some(343));
+		// Moving a node recomputes the cost
+		assert_ok!(AssetTracking::set_node_location(Origin::root(),2,Coordinates { lat: 51_507_400, lon: -127_800 }));
+		assert_eq!(AssetTracking::route_costs(1,2),Some(1));
+		// Manual overrides stick
+		assert_ok!(AssetTracking::update_neighbour(Origin::root(),1,2,25));
+		assert_ok!(AssetTracking::set_node_location(Origin::root(),2,Coordinates { lat: 48_856_600, lon: 2_352_200 }));
+		assert_eq!(AssetTracking::route_costs(1,2),Some(25));
+		assert_eq!(AssetTracking::edge_mode(1,2),None);
+	});
+}
+
+#[test]
+fn it_routes_only_through_capable_nodes() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(1,10),(2,10)]));
+		// Destination cannot take hazardous goods
+		assert_ok!(AssetTracking::set_node_capabilities(Origin::root(),1,bounded_vec![HandlingClass::Hazardous]));
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::Hazardous,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		Error::<Test>::NoCompliantRoute);
+		// Node 3 is never picked as a stop since it only handles refrigerated goods
+		assert_ok!(AssetTracking::set_node_capabilities(Origin::root(),2,bounded_vec![HandlingClass::Hazardous]));
+		assert_ok!(AssetTracking::set_node_capabilities(Origin::root(),3,bounded_vec![HandlingClass::Refrigerated]));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::Hazardous,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.route.into_inner(),vec![1,2]);
+		assert_eq!(shipment.handling,HandlingClass::Hazardous);
+	});
+}
+
+#[test]
+fn it_respects_node_capacity() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::set_node_capacity(Origin::root(),1,Some(1)));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::node_holdings(1),1);
+		// Node 1 is full
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		Error::<Test>::RouteCapacityExhausted);
+		// Delivering the first shipment frees the slot
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None));
+		assert_eq!(AssetTracking::node_holdings(1),0);
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+	});
+}
+
+#[test]
+fn it_prices_routes_only_when_fees_are_enabled() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![]));
+		// There is no edge between the nodes, so the route can't be priced
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		Error::<Test>::InvalidRoute);
+		FeePerCostUnit::set(0);
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().fees,Some(0));
+		assert_eq!(Balances::free_balance(1),10_000 - 100);
+	});
+}
+
+#[test]
+fn it_resets_edge_throughput_every_era() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::set_edge_capacity(Origin::root(),1,2,Some(1)));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::edge_throughput(&1,&2),1);
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		Error::<Test>::RouteCapacityExhausted);
+		// Era length is 10 blocks in the mock
+		System::set_block_number(10);
+		assert_eq!(AssetTracking::edge_throughput(&1,&2),0);
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+	});
+}
+
+#[test]
+fn it_uses_precomputed_shortest_routes() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(1,50),(2,10)]));
+		let version = AssetTracking::topology_version();
+		let vectors = AssetTracking::compute_route_vectors();
+		assert_eq!(vectors.iter().map(|(source,routes)| (*source,routes.len())).collect::<Vec<_>>(),vec![(1,2),(2,2),(3,2)]);
+		let routes = |source: u64| vectors.iter().find(|(from,_)| *from == source).unwrap().1.clone();
+		assert!(routes(1).contains(&(3,bounded_vec![1,2,3])));
+		assert!(routes(3).contains(&(1,bounded_vec![3,2,1])));
+		let edges = |source: u64| AssetTracking::route_vector_edges(&source,&routes(source));
+		// Routes must follow existing edges
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,1,bounded_vec![(3,bounded_vec![1,4,3])],edges(1)),
+		Error::<Test>::InvalidRoute);
+		// Nor may they be longer than the shortest path, or leave connected nodes out
+		let longer = routes(1).into_iter()
+			.map(|(to,path)| if to == 3 { (3,bounded_vec![1,3]) } else { (to,path) })
+			.collect::<Vec<_>>();
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,1,longer.try_into().unwrap(),edges(1)),
+		Error::<Test>::InvalidRoute);
+		let partial = routes(3).into_iter().filter(|(to,_)| *to != 1).collect::<Vec<_>>();
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,3,partial.try_into().unwrap(),edges(3)),
+		Error::<Test>::InvalidRoute);
+		// The weight is paid for the edges declared, so reading more is refused
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,1,routes(1),edges(1) - 1),
+		Error::<Test>::RouteEdgesUnderstated);
+
+		// The routes only take effect once every node's routes are in
+		assert_ok!(AssetTracking::submit_route_vectors(Origin::none(),version,1,routes(1),edges(1)));
+		assert_eq!(AssetTracking::route_vec(1,3),Some(vec![1,2,3]));
+		assert_ne!(AssetTracking::route_vector_version(),version);
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,1,routes(1),edges(1)),
+		Error::<Test>::StaleRouteVectors);
+		assert_ok!(AssetTracking::submit_route_vectors(Origin::none(),version,2,routes(2),edges(2)));
+		assert_ok!(AssetTracking::submit_route_vectors(Origin::none(),version,3,routes(3),edges(3)));
+		assert_eq!(AssetTracking::route_vector_version(),version);
+		System::assert_last_event(Event::AssetTracking(crate::Event::RouteVectorsUpdated(version)));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().route.into_inner(),vec![1,2,3]);
+		// Any topology change makes the vectors stale
+		assert_ok!(AssetTracking::update_neighbour(Origin::root(),1,3,5));
+		assert_noop!(AssetTracking::submit_route_vectors(Origin::none(),version,2,routes(2),edges(2)),
+		Error::<Test>::StaleRouteVectors);
+	});
+}
+
+#[test]
+fn it_waits_for_consignee_confirmation() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,Some(7),HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,Some(7),HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		for uid in 1..=2 {
+			assert_ok!(AssetTracking::update_shipment(Origin::signed(2),uid,HandoffProof::Key(KEY),None,None));
+			assert_eq!(AssetTracking::uid_to_shipment(uid).unwrap().status,ShipmentStatus::AwaitingConfirmation);
+		}
+		// Only the consignee can confirm
+		assert_noop!(AssetTracking::confirm_receipt(Origin::signed(2),1),Error::<Test>::UnauthorizedCaller);
+		assert_ok!(AssetTracking::confirm_receipt(Origin::signed(7),1));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Delivered);
+		assert_noop!(AssetTracking::confirm_receipt(Origin::signed(7),1),
+		Error::<Test>::ShipmentNotAwaitingConfirmation);
+		// The second one is confirmed automatically once the window has passed
+		AssetTracking::on_initialize(System::block_number() + 5);
+		assert_eq!(AssetTracking::uid_to_shipment(2).unwrap().status,ShipmentStatus::Delivered);
+	});
+}
+
+#[test]
+fn it_logs_every_handoff() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		Timestamp::set_timestamp(1_000);
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		System::set_block_number(3);
+		Timestamp::set_timestamp(5_000);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None));
+		assert_eq!(AssetTracking::custody_log(1).into_inner(),vec![
+			CustodyEntry { handler: 1, block: 0, moment: 1_000, hop: 0 },
+			CustodyEntry { handler: 2, block: 3, moment: 5_000, hop: 1 },
+		]);
+	});
+}
+
+#[test]
+fn it_reaps_closed_shipments() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(Balances::reserved_balance(1),100 + 9);
+		assert_noop!(AssetTracking::reap_shipment(Origin::signed(5),1),Error::<Test>::ShipmentNotClosed);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None));
+		assert_eq!(Balances::reserved_balance(1),100);
+		assert_noop!(AssetTracking::reap_shipment(Origin::signed(5),1),Error::<Test>::RetentionPeriodNotElapsed);
+		// Anyone can reap once the retention period has passed
+		System::set_block_number(20);
+		assert_ok!(AssetTracking::reap_shipment(Origin::signed(5),1));
+		assert_eq!(AssetTracking::uid_to_shipment(1),None);
+		assert_eq!(AssetTracking::custody_log(1).len(),0);
+		assert_eq!(Balances::reserved_balance(1),0);
+		let archive = AssetTracking::archived_shipments();
+		assert_eq!(archive.len(),1);
+		assert_eq!(archive[0].uid,1);
+		assert_eq!(archive[0].status,ShipmentStatus::Delivered);
+	});
+}
+
+#[test]
+fn it_only_stores_key_commitments() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),Some(commitment(KEY)));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key([2; 16]),Some(commitment([2; 16])),None),
+		Error::<Test>::InvalidKey);
+		// An intermediate node has to commit to the key it seals for the next hop
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None),
+		Error::<Test>::KeyCommitmentRequired);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),Some(commitment([2; 16])));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key(KEY),None,None),Error::<Test>::InvalidKey);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Delivered);
+	});
+}
+
+#[test]
+fn it_requires_sealed_keys_for_registered_nodes() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		let public = crypto::Public::from(sp_core::ed25519::Public([3; 32]));
+		assert_noop!(AssetTracking::register_handoff_key(Origin::signed(9),public.clone()),
+		Error::<Test>::TransitPointNotFound);
+		assert_ok!(AssetTracking::register_handoff_key(Origin::signed(2),public.clone()));
+		assert_eq!(AssetTracking::handoff_key(2),Some(public));
+		// Node 2 registered a key so the key for it has to be sealed
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		Error::<Test>::SealedKeyRequired);
+		let sealed = SealedKey { ephemeral: [4; 32], ciphertext: [5; 16], tag: [6; 16] };
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),Some(sealed),None));
+		assert_eq!(AssetTracking::sealed_key(1),Some(sealed));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None));
+		assert_eq!(AssetTracking::sealed_key(1),None);
+	});
+}
+
+#[test]
+fn it_rotates_expired_keys_sealed_to_the_next_holder() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		let seed = [7; 32];
+		let public = sp_core::ed25519::Pair::from_seed(&seed).public();
+		assert_ok!(AssetTracking::register_handoff_key(Origin::signed(2),crypto::Public::from(public)));
+		let sealed = sealing::seal(&public.0,&[8; 32],&KEY).unwrap();
+		assert_eq!(sealing::open(&seed,&sealed),Some(KEY));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),Some(sealed),None));
+
+		// The expired key is replaced with a fresh one only node 2 can open
+		System::set_block_number(16);
+		AssetTracking::on_initialize(16);
+		System::assert_last_event(Event::AssetTracking(crate::Event::HandoffKeyRotated(1)));
+		assert_eq!(AssetTracking::key_expires_at(1),Some(31));
+		let rotated = sealing::open(&seed,&AssetTracking::sealed_key(1).unwrap()).unwrap();
+		assert_ne!(rotated,KEY);
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),Some(commitment(rotated)));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None),
+		Error::<Test>::InvalidKey);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(rotated),None,None));
+	});
+}
+
+#[test]
+fn it_hands_off_with_mutual_signatures() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,5)]));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::MutualSignature,None,None,None));
+		assert_eq!(AssetTracking::shipment_uid_to_key(1), None);
+
+		let payload = AssetTracking::handoff_payload(1, 1, 1);
+		let signed = |account: u64| TestSignature(account, payload.clone());
+
+		// A key cannot stand in for the signatures
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None),
+			Error::<Test>::InvalidHandoffProof);
+		// Both the releasing and the receiving node have to sign
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,
+			HandoffProof::Signatures { at: 1, releaser: signed(2), receiver: signed(2) },None,None),
+			Error::<Test>::InvalidSignature);
+		// Signatures for another hop are rejected
+		let other_hop = AssetTracking::handoff_payload(1, 2, 1);
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,
+			HandoffProof::Signatures { at: 1, releaser: TestSignature(1, other_hop.clone()), receiver: TestSignature(2, other_hop) },None,None),
+			Error::<Test>::InvalidSignature);
+		// So are signatures over the bare fields, without the handoff context
+		let bare = (1u64, 1u8, 1u64).encode();
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,
+			HandoffProof::Signatures { at: 1, releaser: TestSignature(1, bare.clone()), receiver: TestSignature(2, bare) },None,None),
+			Error::<Test>::InvalidSignature);
+
+		System::set_block_number(1 + 10 + 1);
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,
+			HandoffProof::Signatures { at: 1, releaser: signed(1), receiver: signed(2) },None,None),
+			Error::<Test>::HandoffExpired);
+
+		System::set_block_number(5);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,
+			HandoffProof::Signatures { at: 1, releaser: signed(1), receiver: signed(2) },None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status, ShipmentStatus::Delivered);
+	});
+}
+
+#[test]
+fn it_applies_offline_vouchers_in_order() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		Timestamp::set_timestamp(1_000);
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::MutualSignature,None,None,None));
+
+		// The route is 1 -> 2 -> 3, so hop `n` is signed by nodes `n` and `n + 1`
+		let voucher = |hop: u8, moment: u64| {
+			let payload = AssetTracking::voucher_payload(1, hop, moment);
+			HandoffVoucher {
+				shipment_uid: 1,
+				hop,
+				moment,
+				releaser: TestSignature(hop as u64, payload.clone()),
+				receiver: TestSignature(hop as u64 + 1, payload),
+			}
+		};
+
+		System::set_block_number(4);
+		Timestamp::set_timestamp(10_000);
+		assert_noop!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![voucher(2,3_000),voucher(1,2_000)]),
+		Error::<Test>::VoucherOutOfOrder);
+		// Local timestamps can't go back in time or lie in the future
+		assert_noop!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![voucher(1,500)]),
+		Error::<Test>::VoucherOutOfOrder);
+		assert_noop!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![voucher(1,20_000)]),
+		Error::<Test>::VoucherOutOfOrder);
+		let mut forged = voucher(1,2_000);
+		forged.receiver = TestSignature(3, AssetTracking::voucher_payload(1, 1, 2_000));
+		assert_noop!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![forged]),
+		Error::<Test>::InvalidSignature);
+
+		assert_ok!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![voucher(1,2_000),voucher(2,3_000)]));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Delivered);
+		assert_eq!(AssetTracking::custody_log(1).into_inner(),vec![
+			CustodyEntry { handler: 1, block: 0, moment: 1_000, hop: 0 },
+			CustodyEntry { handler: 2, block: 4, moment: 2_000, hop: 1 },
+			CustodyEntry { handler: 3, block: 4, moment: 3_000, hop: 2 },
+		]);
+		assert_eq!(AssetTracking::consumed_voucher(1,BlakeTwo256::hash(&AssetTracking::voucher_payload(1, 1, 2_000))),Some(4));
+	});
+}
+
+#[test]
+fn it_reissues_and_expires_handoff_keys() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::key_expires_at(1),Some(16));
+
+		// Only the creator or root can replace a lost key
+		assert_noop!(AssetTracking::reissue_key(Origin::signed(2),1,commitment([2; 16]),None),
+		Error::<Test>::UnauthorizedCaller);
+		assert_ok!(AssetTracking::reissue_key(Origin::signed(1),1,commitment([2; 16]),None));
+		System::assert_last_event(Event::AssetTracking(crate::Event::HandoffKeyReissued(1)));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None),
+		Error::<Test>::InvalidKey);
+
+		// The reissued key expires `KeyLifetime` blocks after it was issued
+		System::set_block_number(5);
+		assert_ok!(AssetTracking::reissue_key(Origin::root(),1,commitment([3; 16]),None));
+		AssetTracking::on_initialize(16);
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),Some(commitment([3; 16])));
+		AssetTracking::on_initialize(20);
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),None);
+		System::assert_last_event(Event::AssetTracking(crate::Event::HandoffKeyExpired(1)));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key([3; 16]),None,None),
+		Error::<Test>::UIDNotFound);
+
+		System::set_block_number(21);
+		assert_ok!(AssetTracking::reissue_key(Origin::signed(1),1,commitment([4; 16]),None));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key([4; 16]),None,None));
+		assert_eq!(AssetTracking::key_expires_at(1),None);
+		assert_noop!(AssetTracking::reissue_key(Origin::signed(1),1,commitment([5; 16]),None),
+		Error::<Test>::NoHandoffKey);
+	});
+}
+
+#[test]
+fn it_returns_failed_deliveries_to_sender() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,20)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,Some(7),HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		// The route 1 -> 2 -> 3 costs 30
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().fees,Some(30));
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 30);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None));
+
+		// The consignee refuses the shipment, so the destination sends it back
+		assert_noop!(AssetTracking::return_to_sender(Origin::signed(2),1,Some(commitment([3; 16])),None),
+		Error::<Test>::UnauthorizedCaller);
+		// A return only starts when the custody log has room for the whole way back
+		let log = AssetTracking::custody_log(1);
+		crate::pallet::CustodyLog::<Test>::mutate(1,|full| while full.try_push(log[0].clone()).is_ok() {});
+		assert_noop!(AssetTracking::return_to_sender(Origin::signed(3),1,Some(commitment([3; 16])),None),
+		Error::<Test>::CustodyLogFull);
+		crate::pallet::CustodyLog::<Test>::insert(1,log);
+		assert_ok!(AssetTracking::return_to_sender(Origin::signed(3),1,Some(commitment([3; 16])),None));
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.status,ShipmentStatus::Returning);
+		assert_eq!(shipment.route.into_inner(),vec![3,2,1]);
+		assert_eq!(shipment.owner_index,1);
+		assert_eq!(shipment.fees,Some(60));
+		// Node 1 was paid for carrying the first leg itself, node 2 for the second
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 60 + 9);
+		assert_eq!(Balances::free_balance(2),10_000 + 18);
+		assert_noop!(AssetTracking::confirm_receipt(Origin::signed(7),1),Error::<Test>::ShipmentNotAwaitingConfirmation);
+
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key([3; 16]),Some(commitment([4; 16])),None));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(1),1,HandoffProof::Key([4; 16]),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Returned);
+		assert_eq!(AssetTracking::shipment_closed_at(1),Some(0));
+		assert_eq!(AssetTracking::node_holdings(2),0);
+		assert_noop!(AssetTracking::return_to_sender(Origin::signed(1),1,None,None),Error::<Test>::ShipmentNotReturnable);
+	});
+}
+
+#[test]
+fn it_enforces_the_shipment_lifecycle() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Created);
+
+		// Only the current holder updates the status, and only to operational states
+		assert_noop!(AssetTracking::set_shipment_status(Origin::signed(2),1,ShipmentStatus::AwaitingPickup),
+		Error::<Test>::UnauthorizedCaller);
+		assert_noop!(AssetTracking::set_shipment_status(Origin::signed(1),1,ShipmentStatus::Delivered),
+		Error::<Test>::InvalidStatusTransition);
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(1),1,ShipmentStatus::AwaitingPickup));
+		System::assert_last_event(Event::AssetTracking(crate::Event::ShipmentStatusChanged(1,
+			ShipmentStatus::Created,ShipmentStatus::AwaitingPickup)));
+
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::AtHub);
+		assert_noop!(AssetTracking::set_shipment_status(Origin::signed(1),1,ShipmentStatus::Cancelled),
+		Error::<Test>::InvalidStatusTransition);
+
+		// Held shipments can't be handed over until they are resumed
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(2),1,ShipmentStatus::OnHold));
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None),
+		Error::<Test>::ShipmentNotMoving);
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(2),1,ShipmentStatus::OutForDelivery));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Delivered);
+		assert_noop!(AssetTracking::set_shipment_status(Origin::root(),1,ShipmentStatus::Lost),
+		Error::<Test>::InvalidStatusTransition);
+
+		// The creator can cancel a shipment until it leaves
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::node_holdings(1),1);
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(1),2,ShipmentStatus::Cancelled));
+		assert_eq!(AssetTracking::node_holdings(1),0);
+		assert_eq!(AssetTracking::shipment_uid_to_key(2),None);
+		assert_eq!(AssetTracking::shipment_closed_at(2),Some(1));
+	});
+}
+
+#[test]
+fn it_migrates_old_shipment_statuses() {
+	new_test_ext().execute_with(|| {
+		// The original layout: creator, fees, owner_index, route, destination, uid and a
+		// status of `Failed`, with the handoff key stored in the clear
+		System::set_block_number(3);
+		Timestamp::set_timestamp(1_000);
+		let old = (1u64, None::<Balance>, 2u8, vec![1u64,2,3], 3u64, 1u64, 2u8);
+		unhashed::put(&crate::pallet::UIDToShipment::<Test>::hashed_key_for(1),&old);
+		unhashed::put(&crate::pallet::UIDToKey::<Test>::hashed_key_for(1),&KEY);
+		// A delivered shipment
+		let old = (1u64, None::<Balance>, 0u8, vec![1u64,2], 2u64, 2u64, 1u8);
+		unhashed::put(&crate::pallet::UIDToShipment::<Test>::hashed_key_for(2),&old);
+		StorageVersion::new(0).put::<AssetTracking>();
+
+		AssetTracking::on_runtime_upgrade();
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.status,ShipmentStatus::OnHold);
+		assert_eq!((shipment.handling,shipment.consignee,shipment.handoff_mode),(HandlingClass::General,None,HandoffMode::SharedKey));
+		assert_eq!(shipment.route.into_inner(),vec![1,2,3]);
+		assert_eq!(shipment.beneficial_owner,1);
+		assert_eq!(AssetTracking::title_history(1).len(),1);
+		// Every hop up to the holder is logged as of the upgrade, and the holder holds it
+		assert_eq!(AssetTracking::custody_log(1).into_inner(),vec![
+			CustodyEntry { handler: 1, block: 3, moment: 1_000, hop: 0 },
+			CustodyEntry { handler: 2, block: 3, moment: 1_000, hop: 1 },
+		]);
+		assert_eq!(AssetTracking::node_holdings(2),1);
+		assert_eq!(AssetTracking::shipment_closed_at(1),None);
+		// The plaintext key was readable by anyone, so it has to be reissued
+		assert_eq!(AssetTracking::shipment_uid_to_key(1),None);
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key(KEY),None,None),
+		Error::<Test>::UIDNotFound);
+
+		assert_eq!(AssetTracking::uid_to_shipment(2).unwrap().status,ShipmentStatus::Delivered);
+		assert_eq!(AssetTracking::shipment_closed_at(2),Some(3));
+		assert!(AssetTracking::custody_log(2).is_empty());
+		assert_eq!(AssetTracking::on_chain_storage_version(),4);
+	});
+}
+
+#[test]
+fn it_tags_old_edge_throughput_with_the_current_era() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(12);
+		unhashed::put(&crate::pallet::EdgeThroughput::<Test>::hashed_key_for(1,2),&3u32);
+		StorageVersion::new(3).put::<AssetTracking>();
 
+		AssetTracking::on_runtime_upgrade();
+		assert_eq!(crate::pallet::EdgeThroughput::<Test>::get(1,2),(10,3));
+		assert_eq!(AssetTracking::edge_throughput(&1,&2),3);
+		assert_eq!(AssetTracking::on_chain_storage_version(),4);
+	});
+}
 
+#[test]
+fn it_redirects_shipments_in_flight() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),4,bounded_vec![(2,30)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,Some(7),HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+
+		assert_noop!(AssetTracking::amend_destination(Origin::signed(9),1,4,Some(commitment([3; 16])),None),
+		Error::<Test>::UnauthorizedCaller);
+		// The next hop changes, so node 2 has to hand over a new key
+		assert_noop!(AssetTracking::amend_destination(Origin::signed(7),1,4,None,None),
+		Error::<Test>::KeyCommitmentRequired);
+		// The consignee pays for the new leg, 30, and the creator gets back what is held for
+		// the leg it paid for, less the treasury's share
+		assert_ok!(AssetTracking::amend_destination(Origin::signed(7),1,4,Some(commitment([3; 16])),None));
+		System::assert_last_event(Event::AssetTracking(crate::Event::ShipmentRedirected(1,4)));
+		System::assert_has_event(Event::AssetTracking(crate::Event::FeeRefunded(1,9)));
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.route.into_inner(),vec![1,2,4]);
+		assert_eq!(shipment.destination,4);
+		assert_eq!(shipment.fees,Some(20 - 9 + 30));
+		assert_eq!(Balances::free_balance(7),10_000 - 30);
+		assert_eq!(Balances::reserved_balance(7),27);
+		// Node 1 was paid for the first leg, which it carried itself
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 20 + 9 + 9);
+		assert_eq!(AssetTracking::custody_log(1).len(),2);
+
+		// Going back to the original destination refunds the consignee and charges the creator
+		assert_ok!(AssetTracking::amend_destination(Origin::signed(1),1,3,Some(commitment([4; 16])),None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().fees,Some(41 - 27 + 10));
+		assert_eq!(Balances::free_balance(7),10_000 - 3);
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 20 + 9 + 9 - 10);
+		assert_noop!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([3; 16]),None,None),
+		Error::<Test>::InvalidKey);
+
+		// Refunds are what was held for the leg, whatever it costs by now
+		assert_ok!(AssetTracking::update_neighbour(Origin::root(),2,3,500));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::amend_destination(Origin::signed(7),1,4,Some(commitment([5; 16])),None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().fees,Some(24 - 9 + 30));
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 20 + 9 + 9 - 10 + 9);
+		assert_eq!(Balances::free_balance(7),10_000 - 3 - 30);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(4),1,HandoffProof::Key([5; 16]),None,None));
+		assert_eq!(Balances::reserved_balance(7),0);
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::AwaitingConfirmation);
+		assert_noop!(AssetTracking::amend_destination(Origin::signed(7),1,3,None,None),
+		Error::<Test>::ShipmentNotRedirectable);
+	});
+}
+
+#[test]
+fn it_drops_off_at_every_stop() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),4,bounded_vec![(3,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),5,bounded_vec![]));
+		// Stops are only planned along current precomputed routes
+		assert_noop!(AssetTracking::create_multi_drop_shipment(Origin::signed(1),bounded_vec![(4,2),(2,3)],
+		HandlingClass::General,HandoffMode::SharedKey,Some(commitment(KEY)),None),Error::<Test>::StaleRouteVectors);
+		submit_route_vectors();
+		assert_noop!(AssetTracking::create_multi_drop_shipment(Origin::signed(1),bounded_vec![(2,3),(5,1)],
+		HandlingClass::General,HandoffMode::SharedKey,Some(commitment(KEY)),None),Error::<Test>::NoPrecomputedRoute);
+
+		assert_noop!(AssetTracking::create_multi_drop_shipment(Origin::signed(1),bounded_vec![(2,1),(2,1)],
+		HandlingClass::General,HandoffMode::SharedKey,Some(commitment(KEY)),None),Error::<Test>::InvalidRoute);
+		assert_noop!(AssetTracking::create_multi_drop_shipment(Origin::signed(1),bounded_vec![(1,1)],
+		HandlingClass::General,HandoffMode::SharedKey,Some(commitment(KEY)),None),Error::<Test>::InvalidRoute);
+
+		// Node 2 is closer, so it is served first even though it is listed last
+		assert_ok!(AssetTracking::create_multi_drop_shipment(Origin::signed(1),bounded_vec![(4,2),(2,3)],
+		HandlingClass::General,HandoffMode::SharedKey,Some(commitment(KEY)),None));
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.route.into_inner(),vec![1,2,3,4]);
+		assert_eq!(shipment.destination,4);
+		assert_eq!(shipment.fees,Some(30));
+		assert_eq!(AssetTracking::shipment_stops(1).unwrap().iter().map(|stop| (stop.node,stop.items,stop.hop))
+		.collect::<Vec<_>>(),vec![(2,3,1),(4,2,3)]);
+		assert_noop!(AssetTracking::amend_destination(Origin::signed(1),1,3,Some(commitment([2; 16])),None),
+		Error::<Test>::ShipmentNotRedirectable);
+
+		System::set_block_number(2);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::StopDelivered(1,2)));
+		assert_eq!(AssetTracking::shipment_stops(1).unwrap()[0].delivered_at,Some(2));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::AtHub);
+
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),Some(commitment([3; 16])),None));
+		assert_eq!(AssetTracking::shipment_stops(1).unwrap()[1].delivered_at,None);
+		System::set_block_number(3);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(4),1,HandoffProof::Key([3; 16]),None,None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::StopDelivered(1,4)));
+		assert_eq!(AssetTracking::shipment_stops(1).unwrap()[1].delivered_at,Some(3));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().status,ShipmentStatus::Delivered);
+	});
+}
+
+#[test]
+fn it_books_shipments_for_customers() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+
+		assert_noop!(AssetTracking::book_shipment(Origin::signed(9),8,3,HandlingClass::General,None,HandoffMode::SharedKey,None),
+		Error::<Test>::TransitPointNotFound);
+		assert_ok!(AssetTracking::book_shipment(Origin::signed(9),1,3,HandlingClass::General,None,HandoffMode::SharedKey,None));
+		System::assert_last_event(Event::AssetTracking(crate::Event::ShipmentBooked(1,1)));
+		assert_eq!(AssetTracking::shipment_uid(),0);
+
+		assert_noop!(AssetTracking::accept_booking(Origin::signed(2),1,Some(commitment(KEY)),None),
+		Error::<Test>::UnauthorizedCaller);
+		assert_noop!(AssetTracking::accept_booking(Origin::signed(1),1,None,None),
+		Error::<Test>::KeyCommitmentRequired);
+		assert_ok!(AssetTracking::accept_booking(Origin::signed(1),1,Some(commitment(KEY)),None));
+		System::assert_last_event(Event::AssetTracking(crate::Event::BookingAccepted(1,1)));
+		assert_eq!(AssetTracking::bookings(1),None);
+
+		// The pickup node starts the route, the customer pays for it
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.creator,1);
+		assert_eq!(shipment.route.into_inner(),vec![1,2,3]);
+		assert_eq!(Balances::free_balance(9),10_000 - 100 - 20);
+		assert_eq!(Balances::free_balance(1),10_000);
+		assert_eq!(AssetTracking::shipment_deposit(1),Some((9,100)));
+
+		assert_ok!(AssetTracking::book_shipment(Origin::signed(9),1,3,HandlingClass::General,None,HandoffMode::SharedKey,None));
+		assert_noop!(AssetTracking::cancel_booking(Origin::signed(7),2),Error::<Test>::UnauthorizedCaller);
+		assert_ok!(AssetTracking::cancel_booking(Origin::signed(1),2));
+		System::assert_last_event(Event::AssetTracking(crate::Event::BookingCancelled(2)));
+		assert_noop!(AssetTracking::accept_booking(Origin::signed(1),2,Some(commitment(KEY)),None),
+		Error::<Test>::BookingNotFound);
+	});
+}
+
+#[test]
+fn it_transfers_title_without_moving_custody() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().beneficial_owner,1);
+
+		assert_noop!(AssetTracking::transfer_title(Origin::signed(7),1,8,false),Error::<Test>::UnauthorizedCaller);
+		System::set_block_number(2);
+		assert_ok!(AssetTracking::transfer_title(Origin::signed(1),1,7,false));
+		System::assert_last_event(Event::AssetTracking(crate::Event::TitleTransferred(1,7)));
+
+		// The buyer has to accept a two-step transfer before the title moves
+		assert_ok!(AssetTracking::transfer_title(Origin::signed(7),1,8,true));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().beneficial_owner,7);
+		assert_noop!(AssetTracking::accept_title(Origin::signed(9),1),Error::<Test>::UnauthorizedCaller);
+		System::set_block_number(3);
+		assert_ok!(AssetTracking::accept_title(Origin::signed(8),1));
+		assert_noop!(AssetTracking::accept_title(Origin::signed(8),1),Error::<Test>::NoPendingTitleTransfer);
+
+		let shipment = AssetTracking::uid_to_shipment(1).unwrap();
+		assert_eq!(shipment.beneficial_owner,8);
+		assert_eq!(shipment.creator,1);
+		assert_eq!(AssetTracking::custody_log(1).len(),1);
+		assert_eq!(AssetTracking::title_history(1).iter().map(|entry| (entry.owner,entry.block)).collect::<Vec<_>>(),
+		vec![(1,1),(7,2),(8,3)]);
+
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(1),1,ShipmentStatus::Cancelled));
+		assert_noop!(AssetTracking::transfer_title(Origin::signed(8),1,9,false),Error::<Test>::ShipmentAlreadyClosed);
+	});
+}
+
+#[test]
+fn it_mirrors_shipments_into_uniques_items() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_eq!(Uniques::owner(0,1),Some(1));
+		assert_eq!(<Uniques as Inspect<u64>>::attribute(&0,&1,STATUS_ATTRIBUTE),Some(ShipmentStatus::Created.encode()));
+
+		// The item follows the title, not custody
+		assert_ok!(AssetTracking::transfer_title(Origin::signed(1),1,7,false));
+		assert_eq!(Uniques::owner(0,1),Some(7));
+		// Trading the item directly moves the title along with it
+		assert_ok!(AssetTracking::transfer_title(Origin::signed(7),1,9,true));
+		assert_ok!(Uniques::transfer(Origin::signed(7),0,1,8));
+		assert_ok!(AssetTracking::sync_title(Origin::signed(5),1));
+		System::assert_last_event(Event::AssetTracking(crate::Event::TitleTransferred(1,8)));
+		assert_noop!(AssetTracking::transfer_title(Origin::signed(7),1,9,false),Error::<Test>::UnauthorizedCaller);
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().beneficial_owner,8);
+		assert_eq!(AssetTracking::title_history(1).last().map(|entry| entry.owner),Some(8));
+		// The transfer the previous owner proposed lapses
+		assert_noop!(AssetTracking::accept_title(Origin::signed(9),1),Error::<Test>::NoPendingTitleTransfer);
+		assert_ok!(Uniques::transfer(Origin::signed(8),0,1,6));
+
+		// The item is frozen on delivery, with the goods going to whoever held it
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),None,None));
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().beneficial_owner,6);
+		assert_eq!(<Uniques as Inspect<u64>>::attribute(&0,&1,STATUS_ATTRIBUTE),Some(ShipmentStatus::Delivered.encode()));
+		assert!(!<Uniques as Inspect<u64>>::can_transfer(&0,&1));
+		assert_noop!(Uniques::transfer(Origin::signed(6),0,1,8),pallet_uniques::Error::<Test>::Frozen);
+
+		System::set_block_number(21);
+		assert_ok!(AssetTracking::reap_shipment(Origin::signed(9),1));
+		assert_eq!(Uniques::owner(0,1),None);
+	});
+}
+
+#[test]
+fn it_settles_cash_on_delivery() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+
+		// The buyer's payment is held from booking until delivery
+		assert_ok!(AssetTracking::book_shipment(Origin::signed(9),1,3,HandlingClass::General,None,HandoffMode::SharedKey,Some((5,500))));
+		assert_eq!(Balances::reserved_balance(9),500);
+		assert_ok!(AssetTracking::accept_booking(Origin::signed(1),1,Some(commitment(KEY)),None));
+		assert_eq!(AssetTracking::goods_payment(1).map(|payment| payment.amount),Some(500));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_eq!(Balances::free_balance(5),10_000);
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::PaymentReleased(1,5,500)));
+		assert_eq!(Balances::free_balance(5),10_500);
+		assert_eq!(Balances::reserved_balance(9),100);
+		assert_eq!(AssetTracking::goods_payment(1),None);
+
+		// It goes back to the buyer if the shipment is cancelled, independent of the route fee
+		assert_ok!(AssetTracking::book_shipment(Origin::signed(9),1,3,HandlingClass::General,None,HandoffMode::SharedKey,Some((5,500))));
+		assert_ok!(AssetTracking::accept_booking(Origin::signed(1),2,Some(commitment(KEY)),None));
+		assert_ok!(AssetTracking::set_shipment_status(Origin::signed(1),2,ShipmentStatus::Cancelled));
+		System::assert_has_event(Event::AssetTracking(crate::Event::PaymentRefunded(2,9,500)));
+		assert_eq!(Balances::reserved_balance(9),200);
+		assert_eq!(Balances::free_balance(9),10_000 - 500 - 200 - 2 * 20 + 18);
+
+		// And when the booking is withdrawn
+		assert_ok!(AssetTracking::book_shipment(Origin::signed(9),1,3,HandlingClass::General,None,HandoffMode::SharedKey,Some((5,500))));
+		assert_ok!(AssetTracking::cancel_booking(Origin::signed(9),3));
+		assert_eq!(Balances::reserved_balance(9),200);
+	});
+}
 
+#[test]
+fn it_pays_insurance_claims_for_lost_shipments() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		assert_noop!(AssetTracking::bond_node(Origin::signed(9),2_000),Error::<Test>::TransitPointNotFound);
+		assert_ok!(AssetTracking::bond_node(Origin::signed(2),2_000));
+
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		// 5% of the declared value goes into the pool
+		let pool = AssetTracking::insurance_pool();
+		assert_eq!(Balances::free_balance(pool),0);
+		assert_ok!(AssetTracking::insure_shipment(Origin::signed(1),1,20_000));
+		assert_eq!(Balances::free_balance(pool),1_000);
+		assert_noop!(AssetTracking::insure_shipment(Origin::signed(1),1,20_000),Error::<Test>::AlreadyInsured);
+		assert_noop!(AssetTracking::file_claim(Origin::signed(1),1),Error::<Test>::ShipmentNotLost);
+
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_noop!(AssetTracking::unbond_node(Origin::signed(2),2_000),Error::<Test>::NodeHoldingShipments);
+
+		// Only governance declares a shipment lost. The node that lost it covers it from its bond.
+		assert_noop!(AssetTracking::set_shipment_status(Origin::signed(2),1,ShipmentStatus::Lost),
+		Error::<Test>::UnauthorizedCaller);
+		assert_ok!(AssetTracking::set_shipment_status(Origin::root(),1,ShipmentStatus::Lost));
+		System::assert_has_event(Event::AssetTracking(crate::Event::NodeSlashed(2,2_000)));
+		assert_eq!(AssetTracking::node_bond(2),0);
+		assert_eq!(AssetTracking::policy(1).unwrap().recovered,2_000);
+		assert_eq!(Balances::free_balance(pool),3_000);
+
+		// The payout is what was recovered plus half of the rest the pool can spare
+		assert_ok!(AssetTracking::file_claim(Origin::signed(1),1));
+		System::assert_last_event(Event::AssetTracking(crate::Event::ClaimPaid(1,1,2_000 + 250)));
+		assert_eq!(Balances::free_balance(pool),750);
+		assert_eq!(Balances::free_balance(1),10_000 - 100 - 20 + 9 + 9 - 1_000 + 2_250);
+		assert_noop!(AssetTracking::file_claim(Origin::signed(1),1),Error::<Test>::NotInsured);
+
+		// Premiums below the existential deposit are accepted too
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::insure_shipment(Origin::signed(1),2,100));
+		assert_eq!(Balances::free_balance(pool),755);
+		// Nothing is paid for goods that never left the creator
+		assert_ok!(AssetTracking::set_shipment_status(Origin::root(),2,ShipmentStatus::Lost));
+		assert_noop!(AssetTracking::file_claim(Origin::signed(1),2),Error::<Test>::ShipmentNotClaimable);
+	});
+}
+
+#[test]
+fn it_funds_the_insurance_pool_from_premiums_only() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		let pool = AssetTracking::insurance_pool();
+		let issuance = Balances::total_issuance();
+		assert_eq!(Balances::free_balance(pool),0);
+
+		// The first premium tops the empty pool up to the existential deposit
+		assert_ok!(AssetTracking::insure_shipment(Origin::signed(1),1,100));
+		assert_eq!(AssetTracking::policy(1).unwrap().premium,5);
+		assert_eq!(Balances::free_balance(pool),500);
+		assert_ok!(AssetTracking::insure_shipment(Origin::signed(1),2,100));
+		assert_eq!(Balances::free_balance(pool),505);
+		assert_eq!(Balances::free_balance(1),10_000 - 2 * 110 - 505);
+		assert_eq!(Balances::total_issuance(),issuance);
+	});
+}
+
+#[test]
+fn it_routes_a_share_of_fees_to_the_treasury() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,40)]));
+		submit_route_vectors();
+
+		let issuance = Balances::total_issuance();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::FeeCharged(1,50,5)));
+		// 10% of the fee goes to the treasury, the rest is held until the legs are travelled
+		assert_eq!(Balances::free_balance(TREASURY),10_005);
+		assert_eq!(Balances::reserved_balance(1),100 + 45);
+
+		// Each leg is paid to the node that carried the shipment along it. Nothing is burned.
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),1,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::LegFeePaid(1,1,9)));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(3),1,HandoffProof::Key([2; 16]),None,None));
+		System::assert_has_event(Event::AssetTracking(crate::Event::LegFeePaid(1,2,36)));
+		assert_eq!(Balances::free_balance(2),10_000 + 36);
+		assert_eq!(Balances::reserved_balance(1),100);
+		assert_eq!(Balances::total_issuance(),issuance);
+	});
+}
+
+#[test]
+fn it_charges_sponsors_within_their_allowance() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		let create = || AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,Some(8));
+
+		assert_noop!(create(),Error::<Test>::NoSponsorship);
+		assert_ok!(AssetTracking::approve_sponsorship(Origin::signed(8),1,250));
+
+		// The sponsor pays the fee and the deposit, both out of the allowance, and the creator
+		// keeps the goods
+		assert_ok!(create());
+		System::assert_has_event(Event::AssetTracking(crate::Event::ShipmentSponsored(1,8)));
+		assert_eq!(Balances::free_balance(8),10_000 - 100 - 20);
+		assert_eq!(Balances::free_balance(1),10_000);
+		assert_eq!(AssetTracking::uid_to_shipment(1).unwrap().beneficial_owner,1);
+		assert_eq!(AssetTracking::sponsorship(8,1).unwrap().spent,120);
+
+		assert_ok!(create());
+		assert_noop!(create(),Error::<Test>::SponsorshipExceeded);
+
+		// The allowance is renewed every period
+		System::set_block_number(31);
+		assert_ok!(create());
+		assert_ok!(create());
+		assert_eq!(AssetTracking::sponsorship(8,1).unwrap().spent,240);
+
+		// The leg a returned shipment never travels is refunded and credited back to the
+		// allowance. Returns are paid out of the allowance as well, and by the creator beyond it.
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),3,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_ok!(AssetTracking::update_shipment(Origin::signed(2),4,HandoffProof::Key(KEY),Some(commitment([2; 16])),None));
+		assert_ok!(AssetTracking::return_to_sender(Origin::signed(2),3,Some(commitment([3; 16])),None));
+		assert_eq!(AssetTracking::sponsorship(8,1).unwrap().spent,240 - 9 + 10);
+		assert_eq!(Balances::free_balance(8),10_000 - 4 * 120 + 9 - 10);
+
+		assert_ok!(AssetTracking::revoke_sponsorship(Origin::signed(8),1));
+		assert_noop!(AssetTracking::revoke_sponsorship(Origin::signed(8),1),Error::<Test>::NoSponsorship);
+		assert_ok!(AssetTracking::return_to_sender(Origin::signed(2),4,Some(commitment([3; 16])),None));
+		assert_eq!(Balances::free_balance(8),10_000 - 4 * 120 + 9 - 10 + 9);
+		// Node 1 was paid for the first leg of both shipments
+		assert_eq!(Balances::free_balance(1),10_000 + 2 * 9 - 10);
+	});
+}
+
+#[test]
+fn it_refunds_fees_for_valid_handoffs() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+
+		// Runs a handoff through the extension like block execution does; the call's fee is 10
+		let handoff = |who: u64, shipment_uid: u64, key: [u8; 16]| {
+			let call = Call::AssetTracking(crate::Call::update_shipment {
+				shipment_uid,
+				proof: HandoffProof::Key(key),
+				next_key_commitment: None,
+				next_sealed_key: None,
+			});
+			let info = DispatchInfo::default();
+			let extension = FeelessHandoff::<Test, ChargeTransactionPayment<Test>>::from(ChargeTransactionPayment::from(0));
+			let pre = extension.pre_dispatch(&who,&call,&info,10).unwrap();
+			let result = call.dispatch(Origin::signed(who));
+			let post_info = result.unwrap_or_else(|err| err.post_info);
+			let result = result.map(|_| ()).map_err(|err| err.error);
+			assert_ok!(FeelessHandoff::<Test, ChargeTransactionPayment<Test>>::post_dispatch(Some(pre),&info,&post_info,10,&result));
+			result
+		};
+
+		// Failing handoffs pay
+		assert_eq!(handoff(2,1,[9; 16]),Err(Error::<Test>::InvalidKey.into()));
+		assert_eq!(Balances::free_balance(2),10_000 - 10);
+		assert_eq!(AssetTracking::feeless_handoffs(2),(0,0));
+
+		assert_ok!(handoff(2,1,KEY));
+		assert_eq!(Balances::free_balance(2),10_000 - 10);
+		assert_eq!(AssetTracking::feeless_handoffs(2),(0,1));
+
+		// Over the limit of one per era handoffs pay again
+		assert_ok!(handoff(2,2,KEY));
+		assert_eq!(Balances::free_balance(2),10_000 - 20);
+	});
+}
+
+#[test]
+fn it_rejects_invalid_handoffs_before_inclusion() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),3,bounded_vec![(2,10)]));
+		submit_route_vectors();
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None));
+		assert_ok!(AssetTracking::create_shipment(Origin::signed(1),3,HandlingClass::General,None,HandoffMode::MutualSignature,None,None,None));
+
+		let info = DispatchInfo::default();
+		let validate = |who: u64, call: crate::Call<Test>| CheckHandoff::<Test>::new().validate(&who,&Call::AssetTracking(call),&info,0);
+		let handoff = |shipment_uid: u64, key: [u8; 16]| crate::Call::update_shipment {
+			shipment_uid,
+			proof: HandoffProof::Key(key),
+			next_key_commitment: None,
+			next_sealed_key: None,
+		};
+		let tag = |shipment_uid: u64, hop: u8| ("AssetTrackingHandoff", shipment_uid, hop).encode();
+
+		// The route is 1 -> 2 -> 3, so node 2 receives both shipments first
+		assert_eq!(validate(3,handoff(1,KEY)),Err(InvalidTransaction::BadSigner.into()));
+		assert_eq!(validate(2,handoff(1,[9; 16])),Err(InvalidTransaction::BadProof.into()));
+		assert_eq!(validate(2,handoff(1,KEY)).unwrap().provides,vec![tag(1,1)]);
+		assert_eq!(validate(1,crate::Call::accept_title { shipment_uid: 1 }),Ok(ValidTransaction::default()));
+
+		let voucher = |hop: u8| {
+			let payload = AssetTracking::voucher_payload(2, hop, 0);
+			HandoffVoucher {
+				shipment_uid: 2,
+				hop,
+				moment: 0,
+				releaser: TestSignature(hop as u64, payload.clone()),
+				receiver: TestSignature(hop as u64 + 1, payload),
+			}
+		};
+		let batch = |vouchers: Vec<HandoffVoucher<TestSignature, u64>>| crate::Call::submit_handoffs {
+			vouchers: vouchers.try_into().unwrap(),
+		};
+
+		let valid = validate(9,batch(vec![voucher(1),voucher(2)])).unwrap();
+		assert_eq!((valid.provides,valid.requires),(vec![tag(2,1),tag(2,2)],vec![]));
+		assert_eq!(validate(9,batch(vec![voucher(2),voucher(1)])),Err(InvalidTransaction::Call.into()));
+
+		// A later hop waits in the pool for the one before it
+		let valid = validate(9,batch(vec![voucher(2)])).unwrap();
+		assert_eq!((valid.provides,valid.requires),(vec![tag(2,2)],vec![tag(2,1)]));
+		assert_eq!(CheckHandoff::<Test>::new().pre_dispatch(&9,&Call::AssetTracking(batch(vec![voucher(2)])),&info,0),
+			Err(InvalidTransaction::Future.into()));
+
+		let mut forged = voucher(1);
+		forged.receiver = TestSignature(3, AssetTracking::voucher_payload(2, 1, 0));
+		assert_eq!(validate(9,batch(vec![forged])),Err(InvalidTransaction::BadProof.into()));
+
+		assert_ok!(AssetTracking::submit_handoffs(Origin::signed(9),bounded_vec![voucher(1)]));
+		assert_eq!(validate(9,batch(vec![voucher(1)])),Err(InvalidTransaction::Stale.into()));
+		assert_ok!(CheckHandoff::<Test>::new().pre_dispatch(&9,&Call::AssetTracking(batch(vec![voucher(2)])),&info,0));
+	});
+}
+
+#[test]
+fn it_refuses_a_shipment_collection_it_does_not_own() {
+	let mut storage = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	pallet_balances::GenesisConfig::<Test> { balances: vec![(1,10_000)] }.assimilate_storage(&mut storage).unwrap();
+	sp_io::TestExternalities::from(storage).execute_with(|| {
+		// Without the collection from genesis, anyone can claim its id first
+		assert_ok!(Uniques::create(Origin::signed(9),0,9));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),1,bounded_vec![]));
+		assert_ok!(AssetTracking::create_new_transit_node(Origin::root(),2,bounded_vec![(1,10)]));
+		submit_route_vectors();
+		assert_noop!(AssetTracking::create_shipment(Origin::signed(1),2,HandlingClass::General,None,HandoffMode::SharedKey,Some(commitment(KEY)),None,None),
+		sp_runtime::DispatchError::Other("shipment collection is owned by another account"));
+	});
+}
@@ -31,7 +31,7 @@ pub use frame_support::{
 		constants::{BlockExecutionWeight, ExtrinsicBaseWeight, RocksDbWeight, WEIGHT_PER_SECOND},
 		IdentityFee, Weight,
 	},
-	StorageValue,
+	PalletId, StorageValue,
 };
 pub use frame_system::Call as SystemCall;
 pub use pallet_balances::Call as BalancesCall;
@@ -60,6 +60,9 @@ pub type Balance = u128;
 /// Index of a transaction in the chain.
 pub type Index = u32;
 
+/// A timestamp: milliseconds since the unix epoch.
+pub type Moment = u64;
+
 /// A hash of some data used by the chain.
 pub type Hash = sp_core::H256;
 
@@ -230,7 +233,7 @@ parameter_types! {
 
 impl pallet_timestamp::Config for Runtime {
 	/// A timestamp: milliseconds since the unix epoch.
-	type Moment = u64;
+	type Moment = Moment;
 	type OnTimestampSet = Aura;
 	type MinimumPeriod = MinimumPeriod;
 	type WeightInfo = ();
@@ -258,17 +261,114 @@ impl pallet_transaction_payment::Config for Runtime {
 	type FeeMultiplierUpdate = ();
 }
 
+parameter_types! {
+	pub const TreasuryPalletId: PalletId = PalletId(*b"py/trsry");
+	pub const ProposalBond: Permill = Permill::from_percent(5);
+	pub const ProposalBondMinimum: Balance = 1_000_000_000_000;
+	pub const SpendPeriod: BlockNumber = DAYS;
+	pub const Burn: Permill = Permill::zero();
+}
+
+impl pallet_treasury::Config for Runtime {
+	type PalletId = TreasuryPalletId;
+	type Currency = Balances;
+	type ApproveOrigin = frame_system::EnsureRoot<AccountId>;
+	type RejectOrigin = frame_system::EnsureRoot<AccountId>;
+	type Event = Event;
+	type OnSlash = Treasury;
+	type ProposalBond = ProposalBond;
+	type ProposalBondMinimum = ProposalBondMinimum;
+	type ProposalBondMaximum = ();
+	type SpendPeriod = SpendPeriod;
+	type Burn = Burn;
+	type BurnDestination = ();
+	type SpendFunds = ();
+	type WeightInfo = pallet_treasury::weights::SubstrateWeight<Runtime>;
+	type MaxApprovals = ConstU32<100>;
+}
+
 impl pallet_sudo::Config for Runtime {
 	type Event = Event;
 	type Call = Call;
 }
 
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Runtime
+where
+	Call: From<C>,
+{
+	type OverarchingCall = Call;
+	type Extrinsic = UncheckedExtrinsic;
+}
+
+parameter_types! {
+	pub const TrackerEraLength: BlockNumber = DAYS;
+	pub const AutoConfirmPeriod: BlockNumber = 3 * DAYS;
+	pub const ShipmentDeposit: Balance = 1_000_000_000;
+	pub const ShipmentRetentionPeriod: BlockNumber = 30 * DAYS;
+	pub const HandoffValidity: BlockNumber = HOURS;
+	pub const HandoffKeyLifetime: BlockNumber = 7 * DAYS;
+	// Shipment fees are opt-in: set a price per unit of route cost to charge them
+	pub const ShipmentFeePerCostUnit: Balance = 0;
+	pub const ShipmentCollection: u32 = 0;
+	pub const ShipmentTrackerPalletId: PalletId = PalletId(*b"trk/ship");
+	pub const InsurancePalletId: PalletId = PalletId(*b"trk/insr");
+	pub const InsurancePremium: Permill = Permill::from_percent(1);
+	pub const InsuranceMaxClaimShare: Permill = Permill::from_percent(10);
+	pub const ShipmentFeeTreasuryShare: Permill = Permill::from_percent(10);
+	pub const ShipmentSponsorshipPeriod: BlockNumber = 30 * DAYS;
+}
+
 /// Configure the pallet-template in pallets/template.
 impl pallet_template::Config for Runtime {
 	type Event = Event;
 	type Currency = Balances;
 	type KeyRandomNess = RandomnessCollectiveFlip;
 	type MaxSize = frame_support::traits::ConstU32<100>;
+	type EraLength = TrackerEraLength;
+	type AutoConfirmPeriod = AutoConfirmPeriod;
+	type ShipmentDeposit = ShipmentDeposit;
+	type RetentionPeriod = ShipmentRetentionPeriod;
+	type MaxArchivedShipments = ConstU32<1000>;
+	type Signature = Signature;
+	type Signer = <Signature as Verify>::Signer;
+	type HandoffValidity = HandoffValidity;
+	type MaxHandoffBatch = ConstU32<50>;
+	type KeyLifetime = HandoffKeyLifetime;
+	type FeePerCostUnit = ShipmentFeePerCostUnit;
+	type ShipmentItems =
+		pallet_template::nft::UniquesItems<Runtime, ShipmentCollection, ShipmentTrackerPalletId>;
+	type InsurancePalletId = InsurancePalletId;
+	type InsurancePremium = InsurancePremium;
+	type MaxClaimShare = InsuranceMaxClaimShare;
+	type FeeDestination = Treasury;
+	type TreasuryShare = ShipmentFeeTreasuryShare;
+	type SponsorshipPeriod = ShipmentSponsorshipPeriod;
+	type MaxFeelessHandoffs = ConstU32<1000>;
+}
+
+parameter_types! {
+	pub const UniquesClassDeposit: Balance = 100_000_000_000;
+	pub const UniquesInstanceDeposit: Balance = 1_000_000_000;
+	pub const UniquesMetadataDepositBase: Balance = 1_000_000_000;
+	pub const UniquesDepositPerByte: Balance = 10_000_000;
+}
+
+impl pallet_uniques::Config for Runtime {
+	type Event = Event;
+	type ClassId = u32;
+	type InstanceId = u64;
+	type Currency = Balances;
+	// Root has to be able to create the shipment collection
+	type ForceOrigin = frame_system::EnsureRoot<AccountId>;
+	type ClassDeposit = UniquesClassDeposit;
+	type InstanceDeposit = UniquesInstanceDeposit;
+	type MetadataDepositBase = UniquesMetadataDepositBase;
+	type AttributeDepositBase = UniquesMetadataDepositBase;
+	type DepositPerByte = UniquesDepositPerByte;
+	type StringLimit = ConstU32<128>;
+	type KeyLimit = ConstU32<32>;
+	type ValueLimit = ConstU32<64>;
+	type WeightInfo = pallet_uniques::weights::SubstrateWeight<Runtime>;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
@@ -286,6 +386,8 @@ construct_runtime!(
 		Balances: pallet_balances,
 		TransactionPayment: pallet_transaction_payment,
 		Sudo: pallet_sudo,
+		Treasury: pallet_treasury,
+		Uniques: pallet_uniques,
 		// Include the custom logic from the pallet-template in the runtime.
 		AssetTracking: pallet_template,
 	}
@@ -306,7 +408,13 @@ pub type SignedExtra = (
 	frame_system::CheckEra<Runtime>,
 	frame_system::CheckNonce<Runtime>,
 	frame_system::CheckWeight<Runtime>,
-	pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+	// Refunds transaction fees for valid handoffs by transit nodes
+	pallet_template::extensions::FeelessHandoff<
+		Runtime,
+		pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+	>,
+	// Rejects invalid handoffs before they enter the transaction pool
+	pallet_template::extensions::CheckHandoff<Runtime>,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
@@ -446,6 +554,20 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_template_runtime_api::AssetTrackingApi<Block, AccountId, BlockNumber, Moment> for Runtime {
+		fn custody_log(shipment_uid: u64) -> Vec<pallet_template::CustodyEntry<AccountId, BlockNumber, Moment>> {
+			AssetTracking::custody_log(shipment_uid).into_inner()
+		}
+
+		fn sealed_key(shipment_uid: u64) -> Option<pallet_template::SealedKey> {
+			AssetTracking::sealed_key(shipment_uid)
+		}
+
+		fn title_history(shipment_uid: u64) -> Vec<pallet_template::TitleEntry<AccountId, BlockNumber>> {
+			AssetTracking::title_history(shipment_uid).into_inner()
+		}
+	}
+
 	impl frame_system_rpc_runtime_api::AccountNonceApi<Block, AccountId, Index> for Runtime {
 		fn account_nonce(account: AccountId) -> Index {
 			System::account_nonce(account)